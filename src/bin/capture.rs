@@ -0,0 +1,37 @@
+#![deny(warnings)]
+
+/// This little test binary captures its own stdout or stderr (whichever is
+/// given as its argument) with os_pipe's capture_* functions, writes to it
+/// both through Rust's buffered stream and through a raw handle, and then
+/// prints what it captured to the original stream. Capturing inside the
+/// test process itself would swallow output from the test harness.
+extern crate os_pipe;
+
+use std::env::args;
+use std::io::prelude::*;
+
+fn main() {
+    let stream = args().nth(1).unwrap();
+    let (guard, mut reader) = if stream == "stdout" {
+        os_pipe::capture_stdout().unwrap()
+    } else {
+        os_pipe::capture_stderr().unwrap()
+    };
+    // print! doesn't flush without a newline, so this also checks that the
+    // guard flushes before it restores stdout.
+    if stream == "stdout" {
+        os_pipe::dup_stdout().unwrap().write_all(b"foo").unwrap();
+        print!("bar");
+    } else {
+        os_pipe::dup_stderr().unwrap().write_all(b"foo").unwrap();
+        eprint!("bar");
+    }
+    drop(guard);
+    let mut captured = String::new();
+    reader.read_to_string(&mut captured).unwrap();
+    if stream == "stdout" {
+        print!("captured: {}", captured);
+    } else {
+        eprint!("captured: {}", captured);
+    }
+}
@@ -0,0 +1,15 @@
+#![deny(warnings)]
+
+/// This little test binary copies the file named by its first argument to the
+/// file named by its second argument. We give it paths from os_pipe's
+/// as_child_path, to test that the child can open them like ordinary files.
+use std::env::args;
+use std::fs::{File, OpenOptions};
+use std::io::copy;
+
+fn main() {
+    let args: Vec<String> = args().collect();
+    let mut input = File::open(&args[1]).unwrap();
+    let mut output = OpenOptions::new().write(true).open(&args[2]).unwrap();
+    copy(&mut input, &mut output).unwrap();
+}
@@ -0,0 +1,23 @@
+#![deny(warnings)]
+
+/// This little test binary writes its second argument to the pipe whose raw
+/// fd/handle value is given as its first argument, or found in the environment
+/// variable named by its first argument. The parent makes that pipe
+/// inheritable with os_pipe's set_inheritable or CommandPipeExt, and we use it
+/// to test those along with the from_inherited_* functions.
+extern crate os_pipe;
+
+use std::env::args;
+use std::io::prelude::*;
+
+fn main() {
+    let args: Vec<String> = args().collect();
+    let mut writer = unsafe {
+        match args[1].parse() {
+            Ok(raw) => os_pipe::PipeWriter::from_inherited_raw(raw),
+            Err(_) => os_pipe::PipeWriter::from_inherited_env(&args[1]),
+        }
+    }
+    .unwrap();
+    writer.write_all(args[2].as_bytes()).unwrap();
+}
@@ -0,0 +1,222 @@
+use std::io;
+use std::io::prelude::*;
+use std::thread;
+
+use PipeReader;
+use PipeWriter;
+use PumpHandle;
+
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// What a [`Broadcaster`](struct.Broadcaster.html) does about a consumer
+/// that can't keep up or has gone away.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConsumerPolicy {
+    /// Wait for slow consumers, and stop with the `BrokenPipe` error as soon
+    /// as any consumer closes its end. This is the default.
+    #[default]
+    Fail,
+    /// Wait for slow consumers, but drop consumers that close their end and
+    /// keep going with the rest.
+    DropClosed,
+    /// Never wait. A consumer whose pipe doesn't have room for the next chunk
+    /// is dropped, along with any that close their end. A dropped consumer
+    /// might have received part of that chunk. This puts the writers in
+    /// non-blocking mode.
+    DropSlow,
+}
+
+#[derive(Debug)]
+struct Consumer {
+    writer: PipeWriter,
+    // Cleared the first time tee fails with this writer, for example because
+    // it's really a file or a tty.
+    can_tee: bool,
+}
+
+/// Copies everything from one pipe into several others, like the `tee`
+/// command. For example, a child's stdout can go to a log and to a live
+/// viewer at the same time.
+///
+/// Add consumers with [`writer`](#method.writer), and then either
+/// [`run`](#method.run) the copy on the current thread or
+/// [`spawn`](#method.spawn) a thread for it. Either way, every consumer is
+/// closed at the end, so they all see EOF. On Linux, this uses `tee(2)` to
+/// copy into each consumer's pipe without going through userspace, and only
+/// reads the data once, to consume it. Elsewhere, it's an ordinary read
+/// followed by a write to each consumer. The reader needs to be in blocking
+/// mode.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// use std::io::prelude::*;
+///
+/// let (reader, mut writer) = os_pipe::pipe()?;
+/// let (mut log_reader, log_writer) = os_pipe::pipe()?;
+/// let (mut view_reader, view_writer) = os_pipe::pipe()?;
+///
+/// let mut broadcaster = os_pipe::Broadcaster::new(reader);
+/// broadcaster.writer(log_writer).writer(view_writer);
+/// let handle = broadcaster.spawn()?;
+/// writer.write_all(b"hello")?;
+/// drop(writer);
+///
+/// let mut log = String::new();
+/// log_reader.read_to_string(&mut log)?;
+/// let mut view = String::new();
+/// view_reader.read_to_string(&mut view)?;
+/// assert_eq!(log, "hello");
+/// assert_eq!(view, "hello");
+/// assert_eq!(handle.join()?, 5);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Broadcaster {
+    reader: PipeReader,
+    consumers: Vec<Consumer>,
+    policy: ConsumerPolicy,
+}
+
+impl Broadcaster {
+    /// Broadcast everything read from `reader`, to no one so far.
+    pub fn new(reader: PipeReader) -> Broadcaster {
+        Broadcaster {
+            reader,
+            consumers: Vec::new(),
+            policy: ConsumerPolicy::default(),
+        }
+    }
+
+    /// Add a consumer.
+    pub fn writer(&mut self, writer: PipeWriter) -> &mut Broadcaster {
+        self.consumers.push(Consumer {
+            writer,
+            can_tee: true,
+        });
+        self
+    }
+
+    /// Set what happens to consumers that are slow or closed. The default is
+    /// [`ConsumerPolicy::Fail`](enum.ConsumerPolicy.html#variant.Fail).
+    pub fn policy(&mut self, policy: ConsumerPolicy) -> &mut Broadcaster {
+        self.policy = policy;
+        self
+    }
+
+    /// Copy until EOF on the reader, and return the number of bytes read. If
+    /// every consumer is dropped by the policy, this returns early, and
+    /// closing the reader means whoever is writing into it gets a
+    /// `BrokenPipe` error.
+    pub fn run(mut self) -> io::Result<u64> {
+        if self.policy == ConsumerPolicy::DropSlow {
+            for consumer in &self.consumers {
+                consumer.writer.set_nonblocking(true)?;
+            }
+        }
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut total = 0;
+        while !self.consumers.is_empty() {
+            let n = self.broadcast_chunk(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            total += n as u64;
+        }
+        Ok(total)
+    }
+
+    /// Run the copy on a new thread, and return a handle to join it.
+    pub fn spawn(self) -> io::Result<PumpHandle> {
+        let thread = thread::Builder::new()
+            .name("os_pipe broadcaster".into())
+            .spawn(move || self.run())?;
+        Ok(PumpHandle { thread })
+    }
+
+    // Copy one chunk to every consumer, and return its size, or 0 for EOF.
+    fn broadcast_chunk(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // How much each consumer has been sent already, by tee.
+        let mut sent = vec![0; self.consumers.len()];
+        let mut len = None;
+        if self.policy != ConsumerPolicy::DropSlow {
+            for (i, consumer) in self.consumers.iter_mut().enumerate() {
+                if !consumer.can_tee {
+                    continue;
+                }
+                // The first successful tee decides the size of the chunk, and
+                // the rest get at most that much. Tee always copies from the
+                // start of the pipe, so the ones that get less are topped up
+                // from the buffer below.
+                let limit = len.unwrap_or(buf.len());
+                match self.reader.tee_into(&consumer.writer, limit) {
+                    Ok(n) => {
+                        sent[i] = n;
+                        if len.is_none() {
+                            if n == 0 {
+                                return Ok(0);
+                            }
+                            len = Some(n);
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                        // Leave it to the write below to apply the policy.
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => consumer.can_tee = false,
+                }
+            }
+        }
+        // Consume the chunk. Everything that tee copied is still in the pipe,
+        // and nothing else is reading it.
+        let n = match len {
+            Some(n) => {
+                self.reader.read_exact(&mut buf[..n])?;
+                n
+            }
+            None => loop {
+                match self.reader.read(buf) {
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    result => break result?,
+                }
+            },
+        };
+        if n == 0 {
+            return Ok(0);
+        }
+        let mut kept = Vec::with_capacity(self.consumers.len());
+        for (consumer, sent) in self.consumers.drain(..).zip(sent) {
+            let rest = &buf[sent..n];
+            let result = if self.policy == ConsumerPolicy::DropSlow {
+                write_nonblocking(&consumer.writer, rest)
+            } else {
+                (&consumer.writer).write_all(rest)
+            };
+            match result {
+                Ok(()) => kept.push(consumer),
+                Err(e) => match (e.kind(), self.policy) {
+                    (io::ErrorKind::BrokenPipe, ConsumerPolicy::Fail) => return Err(e),
+                    (io::ErrorKind::BrokenPipe, _) | (io::ErrorKind::WouldBlock, _) => {}
+                    _ => return Err(e),
+                },
+            }
+        }
+        self.consumers = kept;
+        Ok(n)
+    }
+}
+
+// Write all of buf without blocking, or fail with WouldBlock.
+fn write_nonblocking(mut writer: &PipeWriter, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match writer.write(buf) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WouldBlock)),
+            Ok(n) => buf = &buf[n..],
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use std::io;
+use std::io::prelude::*;
+
+use sys;
+use PipeReader;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum StdStream {
+    Stdout,
+    Stderr,
+}
+
+/// Restores the original stdout or stderr when dropped, returned by
+/// [`capture_stdout`] and [`capture_stderr`].
+///
+/// [`capture_stdout`]: fn.capture_stdout.html
+/// [`capture_stderr`]: fn.capture_stderr.html
+#[derive(Debug)]
+pub struct CaptureGuard {
+    stream: StdStream,
+    // Dropped after the flush below, which restores the original stream.
+    _inner: sys::StdCapture,
+}
+
+impl Drop for CaptureGuard {
+    fn drop(&mut self) {
+        // Anything still in Rust's buffer belongs to the capture.
+        let _ = flush(self.stream);
+        // Now the inner guard puts the original back. After that, nothing
+        // holds the write end, and the reader will see EOF.
+    }
+}
+
+fn flush(stream: StdStream) -> io::Result<()> {
+    match stream {
+        StdStream::Stdout => io::stdout().flush(),
+        StdStream::Stderr => io::stderr().flush(),
+    }
+}
+
+fn capture(stream: StdStream) -> io::Result<(CaptureGuard, PipeReader)> {
+    flush(stream)?;
+    let (reader, writer) = ::pipe()?;
+    let inner = sys::StdCapture::start(stream, writer.0)?;
+    Ok((
+        CaptureGuard {
+            stream,
+            _inner: inner,
+        },
+        reader,
+    ))
+}
+
+/// Redirect this process's own standard output into a new pipe, and return
+/// the read end along with a guard that puts the original back when it's
+/// dropped.
+///
+/// This works below the level of `println!`, so it also captures output from
+/// C libraries linked into the process, and from anything else that writes
+/// to the stdout file descriptor. On Unix, this uses `dup2` to replace file
+/// descriptor 1. On Windows, it uses `SetStdHandle`, which Rust's standard
+/// library and `GetStdHandle` callers respect, but the C runtime's `printf`
+/// doesn't, since it looks up the handle once at startup.
+///
+/// Stdout is shared by the whole process, so this captures output from every
+/// thread, and nesting or overlapping captures from different threads will
+/// restore things in the wrong order. It's meant for tests and other
+/// single-threaded setup code. If more than a pipe buffer's worth of output
+/// might be written before the guard is dropped, read from the pipe on
+/// another thread, or the writes will block forever.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// use std::io::prelude::*;
+///
+/// let (guard, mut reader) = os_pipe::capture_stdout()?;
+/// println!("this goes to the pipe");
+/// drop(guard);
+///
+/// let mut captured = String::new();
+/// reader.read_to_string(&mut captured)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn capture_stdout() -> io::Result<(CaptureGuard, PipeReader)> {
+    capture(StdStream::Stdout)
+}
+
+/// Redirect this process's own standard error into a new pipe, like
+/// [`capture_stdout`](fn.capture_stdout.html).
+pub fn capture_stderr() -> io::Result<(CaptureGuard, PipeReader)> {
+    capture(StdStream::Stderr)
+}
@@ -0,0 +1,193 @@
+use std::io;
+use std::io::prelude::*;
+
+use PipeReader;
+use PipeWriter;
+
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 1 << 24;
+const HEADER_SIZE: usize = 4;
+
+/// The sending half of a message channel, returned by
+/// [`channel`](fn.channel.html).
+///
+/// Each message is written as a 4-byte little-endian length followed by the
+/// message bytes. Any process that speaks that framing can be on the other
+/// end, but usually it's a [`ChannelReceiver`] in a child process, built from
+/// the pipe with [`ChannelReceiver::new`].
+///
+/// [`ChannelReceiver`]: struct.ChannelReceiver.html
+/// [`ChannelReceiver::new`]: struct.ChannelReceiver.html#method.new
+#[derive(Debug)]
+pub struct ChannelSender {
+    writer: PipeWriter,
+    max_message_size: usize,
+}
+
+impl ChannelSender {
+    /// Send messages over an existing pipe, for example one that a parent
+    /// process passed down.
+    pub fn new(writer: PipeWriter) -> ChannelSender {
+        ChannelSender {
+            writer,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Set the largest message that [`send`](#method.send) will accept. The
+    /// default is 16 MiB, and the framing can't go above `u32::MAX`.
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// Send one message. Messages that fit in `PIPE_BUF` along with their
+    /// header are written in a single call, so they won't interleave with
+    /// messages from other senders on the same pipe. This fails with
+    /// `InvalidInput` if the message is over the maximum size.
+    pub fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        if message.len() > self.max_message_size || message.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message larger than the maximum size",
+            ));
+        }
+        let header = (message.len() as u32).to_le_bytes();
+        let mut bufs = [io::IoSlice::new(&header), io::IoSlice::new(message)];
+        let mut bufs = &mut bufs[..];
+        // The first write_vectored carries the header and the message
+        // together. What's left after a short write goes in later calls,
+        // the way write_all would do it with one buffer.
+        while !bufs.is_empty() {
+            match self.writer.write_vectored(bufs) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole message",
+                    ))
+                }
+                Ok(n) => io::IoSlice::advance_slices(&mut bufs, n),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Get a reference to the underlying pipe.
+    pub fn get_ref(&self) -> &PipeWriter {
+        &self.writer
+    }
+
+    /// Return the underlying pipe.
+    pub fn into_inner(self) -> PipeWriter {
+        self.writer
+    }
+}
+
+/// The receiving half of a message channel, returned by
+/// [`channel`](fn.channel.html). See
+/// [`ChannelSender`](struct.ChannelSender.html) for the framing.
+///
+/// The underlying pipe needs to be in blocking mode. A `WouldBlock` error in
+/// the middle of a message would lose the part that was already read.
+#[derive(Debug)]
+pub struct ChannelReceiver {
+    reader: PipeReader,
+    max_message_size: usize,
+}
+
+impl ChannelReceiver {
+    /// Receive messages from an existing pipe, for example one that a parent
+    /// process passed down.
+    pub fn new(reader: PipeReader) -> ChannelReceiver {
+        ChannelReceiver {
+            reader,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Set the largest message that [`recv`](#method.recv) will accept. The
+    /// default is 16 MiB. This keeps a misbehaving sender from making the
+    /// receiver allocate an arbitrary amount of memory.
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// Wait for the next message. This returns `Ok(None)` when every sender
+    /// has been closed, between messages. If the pipe closes in the middle of
+    /// a message, this fails with `UnexpectedEof`, and if a message is over
+    /// the maximum size, this fails with `InvalidData`. After an error, the
+    /// framing can't be trusted anymore, and the channel shouldn't be used
+    /// again.
+    pub fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut header = [0; HEADER_SIZE];
+        if !read_exact_or_eof(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes(header) as usize;
+        if len > self.max_message_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message larger than the maximum size",
+            ));
+        }
+        let mut message = vec![0; len];
+        self.reader.read_exact(&mut message)?;
+        Ok(Some(message))
+    }
+
+    /// Get a reference to the underlying pipe.
+    pub fn get_ref(&self) -> &PipeReader {
+        &self.reader
+    }
+
+    /// Return the underlying pipe.
+    pub fn into_inner(self) -> PipeReader {
+        self.reader
+    }
+}
+
+// Like read_exact, but return false instead of failing if EOF comes before the
+// first byte.
+fn read_exact_or_eof(reader: &mut PipeReader, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// Open a new pipe and wrap it in a channel that sends whole messages, using
+/// length-prefixed framing.
+///
+/// Either half can be passed to a child process with
+/// [`ChannelSender::into_inner`] or [`ChannelReceiver::into_inner`], and
+/// rebuilt on the other side with `new`.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// let (mut sender, mut receiver) = os_pipe::channel()?;
+/// sender.send(b"hello")?;
+/// sender.send(b"world")?;
+/// drop(sender);
+///
+/// assert_eq!(receiver.recv()?, Some(b"hello".to_vec()));
+/// assert_eq!(receiver.recv()?, Some(b"world".to_vec()));
+/// assert_eq!(receiver.recv()?, None);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`ChannelSender::into_inner`]: struct.ChannelSender.html#method.into_inner
+/// [`ChannelReceiver::into_inner`]: struct.ChannelReceiver.html#method.into_inner
+pub fn channel() -> io::Result<(ChannelSender, ChannelReceiver)> {
+    let (reader, writer) = ::pipe()?;
+    Ok((ChannelSender::new(writer), ChannelReceiver::new(reader)))
+}
@@ -0,0 +1,201 @@
+use std::ffi::OsStr;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::prelude::*;
+#[cfg(windows)]
+use std::os::windows::prelude::*;
+use std::process::{Child, Command, Stdio};
+
+use sys;
+use PipeReader;
+use PipeWriter;
+
+/// An extension trait for `std::process::Command`, for giving a child pipes
+/// beyond its stdin, stdout, and stderr, and for spawning it with all three
+/// piped, with [`spawn_with_pipes`].
+///
+/// Some programs expect to be told about an extra pipe, like gpg's
+/// `--status-fd` or the `SSH_ASKPASS` protocols. [`pass_pipe`] makes any pipe
+/// end inheritable by the child and tells the child where to find it, by
+/// setting an environment variable to the pipe's raw fd number or handle
+/// value. On Unix, [`pass_pipe_as_fd`] puts the pipe at a specific fd number
+/// instead, which is what most Unix tools expect.
+///
+/// On Unix, the `Command` takes ownership of the pipe end and keeps it open
+/// until the `Command` is dropped, like it does with `Command::stdout`. So
+/// drop the `Command` once the child is spawned, since the parent holding a
+/// copy of a writer will prevent EOF. Use `try_clone` to keep a copy. On
+/// Windows, `Command` has nowhere to keep a handle, so `pass_pipe` only
+/// borrows the pipe end, and you need to keep it open until the child is
+/// spawned and then drop it.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(unix)]
+/// # fn main() -> std::io::Result<()> {
+/// use os_pipe::CommandPipeExt;
+/// use std::process::Command;
+///
+/// let (reader, writer) = os_pipe::pipe()?;
+/// let mut command = Command::new("some_child");
+/// command.pass_pipe("STATUS_PIPE", writer)?;
+/// // Spawn the child here, and then...
+/// drop(command);
+/// # drop(reader);
+/// # Ok(())
+/// # }
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+///
+/// [`pass_pipe`]: trait.CommandPipeExt.html#tymethod.pass_pipe
+/// [`pass_pipe_as_fd`]: trait.CommandPipeExt.html#tymethod.pass_pipe_as_fd
+/// [`spawn_with_pipes`]: trait.CommandPipeExt.html#tymethod.spawn_with_pipes
+pub trait CommandPipeExt {
+    /// Let the child inherit `pipe`, and set the environment variable `key` to
+    /// its file descriptor number in the child. The child can turn that back
+    /// into a pipe end with [`PipeReader::from_inherited_env`]. This clears
+    /// `FD_CLOEXEC` in the child just before `exec`, so the pipe stays
+    /// non-inheritable in the parent. The `Command` owns `pipe` from now on.
+    ///
+    /// [`PipeReader::from_inherited_env`]: struct.PipeReader.html#method.from_inherited_env
+    #[cfg(unix)]
+    fn pass_pipe<K: AsRef<OsStr>, P: Into<OwnedFd>>(
+        &mut self,
+        key: K,
+        pipe: P,
+    ) -> io::Result<&mut Command>;
+
+    /// Let the child inherit `pipe`, and set the environment variable `key` to
+    /// its handle value. The child can turn that back into a pipe end with
+    /// [`PipeReader::from_inherited_env`].
+    ///
+    /// Windows has no way to mark a handle inheritable for just one child, so
+    /// this sets `HANDLE_FLAG_INHERIT` on `pipe` itself, and other children
+    /// spawned in the meantime will inherit it too. Drop `pipe` or call
+    /// `set_inheritable(false)` on it as soon as the child is spawned, or
+    /// spawn with [`HandleList`](struct.HandleList.html) instead.
+    ///
+    /// [`PipeReader::from_inherited_env`]: struct.PipeReader.html#method.from_inherited_env
+    #[cfg(windows)]
+    fn pass_pipe<K: AsRef<OsStr>, P: AsHandle>(
+        &mut self,
+        key: K,
+        pipe: &P,
+    ) -> io::Result<&mut Command>;
+
+    /// Give the child `pipe` as file descriptor `fd`, using `dup2` just
+    /// before `exec`. The `Command` owns `pipe` from now on. This is
+    /// Unix-only.
+    ///
+    /// These run in the order they're added, after the child's stdin, stdout,
+    /// and stderr are set up. It's fine for one pipe's target to be another
+    /// pipe's fd number, since each pipe is first moved out of the way of the
+    /// targets before it.
+    #[cfg(unix)]
+    fn pass_pipe_as_fd<P: Into<OwnedFd>>(&mut self, fd: RawFd, pipe: P) -> &mut Command;
+
+    /// Spawn the child with new pipes as its stdin, stdout, and stderr, and
+    /// return it along with the parent's ends. Unlike `Stdio::piped`, these
+    /// are this crate's pipe types, with methods like `set_nonblocking` and
+    /// `read_timeout`.
+    ///
+    /// The `Command` would otherwise hold the child's ends of the pipes until
+    /// it's dropped, and then reading the child's output would never reach
+    /// EOF. So this resets its stdin, stdout, and stderr to `Stdio::inherit`
+    /// afterwards, whether or not the spawn succeeded. Drop
+    /// [`PipedChild::stdin`](struct.PipedChild.html#structfield.stdin) to give
+    /// the child EOF. A child that writes a lot to both stdout and stderr
+    /// needs both read at once, for example on separate threads, or with
+    /// [`poll`](fn.poll.html).
+    fn spawn_with_pipes(&mut self) -> io::Result<PipedChild>;
+}
+
+/// A child process and the parent's ends of its pipes, returned by
+/// [`CommandPipeExt::spawn_with_pipes`](trait.CommandPipeExt.html#tymethod.spawn_with_pipes).
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(unix)]
+/// # fn main() -> std::io::Result<()> {
+/// use os_pipe::CommandPipeExt;
+/// use std::io::prelude::*;
+/// use std::process::Command;
+///
+/// let mut piped = Command::new("cat").spawn_with_pipes()?;
+/// piped.stdin.write_all(b"hello")?;
+/// drop(piped.stdin);
+/// let mut output = String::new();
+/// piped.stdout.read_to_string(&mut output)?;
+/// assert_eq!(output, "hello");
+/// piped.child.wait()?;
+/// # Ok(())
+/// # }
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct PipedChild {
+    /// The child process. Its `stdin`, `stdout`, and `stderr` fields are
+    /// `None`, since the pipes are here instead.
+    pub child: Child,
+    /// The writing end of the child's stdin.
+    pub stdin: PipeWriter,
+    /// The reading end of the child's stdout.
+    pub stdout: PipeReader,
+    /// The reading end of the child's stderr.
+    pub stderr: PipeReader,
+}
+
+impl CommandPipeExt for Command {
+    #[cfg(unix)]
+    fn pass_pipe<K: AsRef<OsStr>, P: Into<OwnedFd>>(
+        &mut self,
+        key: K,
+        pipe: P,
+    ) -> io::Result<&mut Command> {
+        let fd = sys::pass_fd(self, pipe.into(), None);
+        self.env(key, fd.to_string());
+        Ok(self)
+    }
+
+    #[cfg(windows)]
+    fn pass_pipe<K: AsRef<OsStr>, P: AsHandle>(
+        &mut self,
+        key: K,
+        pipe: &P,
+    ) -> io::Result<&mut Command> {
+        let handle = pipe.as_handle().as_raw_handle();
+        sys::set_handle_inheritable(handle, true)?;
+        self.env(key, (handle as usize).to_string());
+        Ok(self)
+    }
+
+    #[cfg(unix)]
+    fn pass_pipe_as_fd<P: Into<OwnedFd>>(&mut self, fd: RawFd, pipe: P) -> &mut Command {
+        sys::pass_fd(self, pipe.into(), Some(fd));
+        self
+    }
+
+    fn spawn_with_pipes(&mut self) -> io::Result<PipedChild> {
+        let (stdin_reader, stdin) = ::pipe()?;
+        let (stdout, stdout_writer) = ::pipe()?;
+        let (stderr, stderr_writer) = ::pipe()?;
+        let result = self
+            .stdin(stdin_reader)
+            .stdout(stdout_writer)
+            .stderr(stderr_writer)
+            .spawn();
+        self.stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        Ok(PipedChild {
+            child: result?,
+            stdin,
+            stdout,
+            stderr,
+        })
+    }
+}
@@ -0,0 +1,332 @@
+use std::fs::File;
+use std::io;
+use std::net::Shutdown;
+#[cfg(unix)]
+use std::os::unix::io::{AsFd, OwnedFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsHandle, OwnedHandle};
+use std::process::Stdio;
+
+use sys;
+use PipeReader;
+use PipeWriter;
+
+/// One end of a bidirectional pipe, returned by [`duplex`](fn.duplex.html).
+///
+/// A `Duplex` implements both `Read` and `Write`. Whatever one end writes, the
+/// other end reads. It also implements `Into<Stdio>`, so one end can be given
+/// to a child process (with `try_clone` to use it as both stdin and stdout)
+/// while the parent keeps the other.
+#[derive(Debug)]
+pub struct Duplex(pub(crate) File);
+
+impl Duplex {
+    /// Create a new `Duplex` instance that shares the same underlying
+    /// connection, like
+    /// [`PipeReader::try_clone`](struct.PipeReader.html#method.try_clone).
+    pub fn try_clone(&self) -> io::Result<Duplex> {
+        self.0.try_clone().map(Duplex)
+    }
+
+    /// Close this end of the connection, like
+    /// [`PipeWriter::close`](struct.PipeWriter.html#method.close).
+    pub fn close(self) -> io::Result<()> {
+        sys::close(self.0)
+    }
+
+    /// Close one or both directions of the connection, for every copy of this
+    /// end. After `Shutdown::Write`, the other end reads EOF once it's read
+    /// everything already sent, but it can still write back, and this end can
+    /// still read. That's how to tell a child that its input is done while
+    /// still collecting its output over the same connection. Dropping the
+    /// `Duplex` can't do that, because it closes both directions, and only
+    /// once every copy is closed.
+    ///
+    /// This is `shutdown(2)` on Unix. Windows named pipes can't close one
+    /// direction, so there this returns `Unsupported`. Use two pipes instead.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        sys::shutdown(&self.0, how)
+    }
+
+    /// Send a copy of an open file descriptor to the process on the other end,
+    /// using `sendmsg` with `SCM_RIGHTS`. The other end receives it with
+    /// [`recv_fd`](struct.Duplex.html#method.recv_fd). This is Unix-only, and
+    /// on Fuchsia it always fails with `Unsupported`. On Windows, see
+    /// `send_handle`.
+    ///
+    /// This is how to give a new pipe end to a process that's already
+    /// running, which inheritance can't do. The descriptor rides along with
+    /// one byte of ordinary data, so don't mix this with other reads and
+    /// writes on the same connection unless your protocol keeps them apart.
+    /// The caller's copy stays open, and can be dropped right away.
+    #[cfg(unix)]
+    pub fn send_fd<F: AsFd>(&self, fd: &F) -> io::Result<()> {
+        sys::send_fd(&self.0, fd.as_fd())
+    }
+
+    /// Receive a file descriptor sent by
+    /// [`send_fd`](struct.Duplex.html#method.send_fd) on the other end. The
+    /// new descriptor is close-on-exec, like the pipes from
+    /// [`pipe`](fn.pipe.html). This blocks until something arrives, and fails
+    /// with `UnexpectedEof` if the other end is closed, or `InvalidData` if
+    /// the byte that arrived didn't carry exactly the descriptors that were
+    /// sent. This is Unix-only.
+    #[cfg(unix)]
+    pub fn recv_fd(&self) -> io::Result<OwnedFd> {
+        sys::recv_fd(&self.0)
+    }
+
+    /// Copy `handle` into `target_process` with `DuplicateHandle`, and send
+    /// its new value to the other end, which receives it with
+    /// [`recv_handle`](struct.Duplex.html#method.recv_handle). `target_process`
+    /// needs `PROCESS_DUP_HANDLE` access, which a `std::process::Child`
+    /// always has. This is Windows-only. On Unix, see `send_fd`.
+    ///
+    /// This is how to give a new pipe end to a process that's already
+    /// running, which inheritance can't do. The value is sent as 8 bytes of
+    /// ordinary data, so don't mix this with other reads and writes on the
+    /// same connection unless your protocol keeps them apart. The caller's
+    /// copy stays open, and can be dropped right away.
+    #[cfg(windows)]
+    pub fn send_handle<H: AsHandle, P: AsHandle>(
+        &self,
+        handle: &H,
+        target_process: &P,
+    ) -> io::Result<()> {
+        sys::send_handle(&self.0, handle.as_handle(), target_process.as_handle())
+    }
+
+    /// Receive a handle sent by
+    /// [`send_handle`](struct.Duplex.html#method.send_handle) on the other
+    /// end. The handle is non-inheritable, like the pipes from
+    /// [`pipe`](fn.pipe.html). This blocks until something arrives, and fails
+    /// with `UnexpectedEof` if the other end is closed. This is Windows-only.
+    ///
+    /// # Safety
+    ///
+    /// Unlike `SCM_RIGHTS` on Unix, the OS doesn't deliver the handle here.
+    /// The sender puts it into this process and sends only its value, so
+    /// nothing stops a buggy or malicious sender from naming a handle that
+    /// this process already owns. Only receive from a trusted sender.
+    #[cfg(windows)]
+    pub unsafe fn recv_handle(&self) -> io::Result<OwnedHandle> {
+        sys::recv_handle(&self.0)
+    }
+}
+
+impl io::Read for Duplex {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        sys::read(&self.0, buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        sys::read_vectored(&self.0, bufs)
+    }
+}
+
+impl io::Read for &Duplex {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        sys::read(&self.0, buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        sys::read_vectored(&self.0, bufs)
+    }
+}
+
+impl io::Write for Duplex {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        sys::write(&self.0, buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        sys::write_vectored(&self.0, bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl io::Write for &Duplex {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        sys::write(&self.0, buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        sys::write_vectored(&self.0, bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut file_ref = &self.0;
+        file_ref.flush()
+    }
+}
+
+/// Like the conversions for `PipeReader` and `PipeWriter`, this moves the
+/// connection into the `Stdio` without duplicating it.
+impl From<Duplex> for Stdio {
+    fn from(d: Duplex) -> Stdio {
+        d.0.into()
+    }
+}
+
+/// Open a new bidirectional connection and return both ends.
+///
+/// This corresponds to `socketpair(AF_UNIX, SOCK_STREAM)` on Posix, and a
+/// connected pair of named pipe handles on Windows. Like [`pipe`](fn.pipe.html),
+/// both ends are non-inheritable.
+pub fn duplex() -> io::Result<(Duplex, Duplex)> {
+    let (a, b) = sys::duplex()?;
+    Ok((Duplex(a), Duplex(b)))
+}
+
+/// One side of a pair of pipes, one for each direction, returned by
+/// [`duplex_pipe`](fn.duplex_pipe.html).
+///
+/// Like a [`Duplex`](struct.Duplex.html), a `DuplexPipe` implements both
+/// `Read` and `Write`, and whatever one side writes, the other side reads.
+/// Unlike a `Duplex`, it's made of two ordinary pipes, so the child side can
+/// become a child's stdin and stdout with [`into_stdio`](#method.into_stdio),
+/// which works with programs that expect pipes rather than sockets, and works
+/// the same on every platform.
+#[derive(Debug)]
+pub struct DuplexPipe {
+    reader: PipeReader,
+    writer: PipeWriter,
+}
+
+impl DuplexPipe {
+    /// Get a reference to the pipe this side reads from.
+    pub fn reader(&self) -> &PipeReader {
+        &self.reader
+    }
+
+    /// Get a reference to the pipe this side writes to.
+    pub fn writer(&self) -> &PipeWriter {
+        &self.writer
+    }
+
+    /// Split this side into its two pipes. Dropping the writer, while keeping
+    /// the reader, tells the other side that its input is done, and it can
+    /// still answer.
+    pub fn into_parts(self) -> (PipeReader, PipeWriter) {
+        (self.reader, self.writer)
+    }
+
+    /// Close both pipes, like
+    /// [`PipeWriter::close`](struct.PipeWriter.html#method.close), and return
+    /// the first error. Both are closed even if the first one fails.
+    pub fn close(self) -> io::Result<()> {
+        let writer_result = self.writer.close();
+        let reader_result = self.reader.close();
+        writer_result.and(reader_result)
+    }
+
+    /// Turn this side into a child's stdin and stdout, in that order. For
+    /// example, `command.stdin(stdin).stdout(stdout)`. The `Command` then holds
+    /// the only copies, so drop it once the child is spawned, or the parent
+    /// side never sees EOF.
+    pub fn into_stdio(self) -> (Stdio, Stdio) {
+        (self.reader.into(), self.writer.into())
+    }
+}
+
+impl io::Read for DuplexPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        self.reader.read_vectored(bufs)
+    }
+}
+
+impl io::Read for &DuplexPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&self.reader).read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        (&self.reader).read_vectored(bufs)
+    }
+}
+
+impl io::Write for DuplexPipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        self.writer.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl io::Write for &DuplexPipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&self.writer).write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        (&self.writer).write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&self.writer).flush()
+    }
+}
+
+/// Open two pipes, one in each direction, and return them bundled into two
+/// sides that each read what the other writes. Keep one side, and give the
+/// other to a child with [`DuplexPipe::into_stdio`]. Getting this wrong by
+/// hand, for example by keeping the wrong ends or forgetting to close the
+/// parent's copies of the child's, usually means a deadlock.
+///
+/// Like [`pipe`](fn.pipe.html), all four ends are non-inheritable.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(unix)]
+/// # fn main() -> std::io::Result<()> {
+/// use std::io::prelude::*;
+/// use std::process::Command;
+///
+/// let (parent, child) = os_pipe::duplex_pipe()?;
+/// let (stdin, stdout) = child.into_stdio();
+/// let mut command = Command::new("cat");
+/// let mut handle = command.stdin(stdin).stdout(stdout).spawn()?;
+/// drop(command);
+///
+/// let (mut reader, mut writer) = parent.into_parts();
+/// writer.write_all(b"ping")?;
+/// drop(writer);
+/// let mut output = String::new();
+/// reader.read_to_string(&mut output)?;
+/// assert_eq!(output, "ping");
+/// handle.wait()?;
+/// # Ok(())
+/// # }
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+///
+/// [`DuplexPipe::into_stdio`]: struct.DuplexPipe.html#method.into_stdio
+pub fn duplex_pipe() -> io::Result<(DuplexPipe, DuplexPipe)> {
+    let (reader1, writer1) = ::pipe()?;
+    let (reader2, writer2) = ::pipe()?;
+    Ok((
+        DuplexPipe {
+            reader: reader1,
+            writer: writer2,
+        },
+        DuplexPipe {
+            reader: reader2,
+            writer: writer1,
+        },
+    ))
+}
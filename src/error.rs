@@ -0,0 +1,107 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use sys;
+
+/// The kinds of failure that callers of this crate usually need to tell
+/// apart, each carrying the original `io::Error`.
+///
+/// Everything in this crate still returns `io::Result`, so it works with `?`
+/// alongside the standard library. To sort an error out, convert it with
+/// `Error::from`, and to go back, use `io::Error::from`, which returns the
+/// original error with its `raw_os_error` intact.
+///
+/// The errors themselves are never wrapped, so an error from [`pipe`],
+/// [`PipeOptions::create`], or [`duplex`] has the same `kind`,
+/// `raw_os_error`, and message as the underlying call's. `Error::from` sorts
+/// it out afterwards by looking at those.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// use std::io::prelude::*;
+///
+/// let (reader, mut writer) = os_pipe::pipe()?;
+/// drop(reader);
+/// let err = writer.write(b"x").unwrap_err();
+/// match os_pipe::Error::from(err) {
+///     os_pipe::Error::BrokenPipe(_) => {}
+///     other => panic!("unexpected error: {}", other),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`pipe`]: fn.pipe.html
+/// [`PipeOptions::create`]: struct.PipeOptions.html#method.create
+/// [`duplex`]: fn.duplex.html
+#[derive(Debug)]
+pub enum Error {
+    /// Opening a new pipe or connection failed because the process or the
+    /// system ran out of file descriptors or handles. That's `EMFILE` or
+    /// `ENFILE` on Unix, and `ERROR_TOO_MANY_OPEN_FILES` or
+    /// `ERROR_NO_SYSTEM_RESOURCES` on Windows.
+    Create(io::Error),
+    /// The other end of the pipe is closed, so writing is pointless. (Reading
+    /// from a closed pipe isn't an error. It returns 0 for EOF.)
+    BrokenPipe(io::Error),
+    /// The pipe is in non-blocking mode, and isn't ready.
+    WouldBlock(io::Error),
+    /// The platform doesn't support the operation.
+    Unsupported(io::Error),
+    /// Anything else.
+    Other(io::Error),
+}
+
+impl Error {
+    /// The original error.
+    pub fn io_error(&self) -> &io::Error {
+        match *self {
+            Error::Create(ref e)
+            | Error::BrokenPipe(ref e)
+            | Error::WouldBlock(ref e)
+            | Error::Unsupported(ref e)
+            | Error::Other(ref e) => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.io_error().fmt(f)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(self.io_error())
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        if sys::is_out_of_descriptors(&err) {
+            return Error::Create(err);
+        }
+        match err.kind() {
+            io::ErrorKind::BrokenPipe => Error::BrokenPipe(err),
+            io::ErrorKind::WouldBlock => Error::WouldBlock(err),
+            io::ErrorKind::Unsupported => Error::Unsupported(err),
+            _ => Error::Other(err),
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        match err {
+            Error::Create(e)
+            | Error::BrokenPipe(e)
+            | Error::WouldBlock(e)
+            | Error::Unsupported(e)
+            | Error::Other(e) => e,
+        }
+    }
+}
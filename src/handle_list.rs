@@ -0,0 +1,256 @@
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::windows::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+use sys;
+use PtySlave;
+
+/// Spawns a child that inherits exactly the handles you list, and nothing
+/// else, using `PROC_THREAD_ATTRIBUTE_HANDLE_LIST`. This is Windows-only.
+///
+/// `Command::spawn` and [`CommandPipeExt::pass_pipe`] need the child's handles
+/// to be inheritable, and Windows has no way to mark a handle inheritable for
+/// just one child. Any other child spawned by any thread in the meantime
+/// inherits it too, and a pipe writer that leaks into an unrelated
+/// long-running child means its reader never sees EOF. `HandleList` leaves the
+/// handles you give it alone. At spawn time it makes inheritable duplicates,
+/// lists only those for the new child, and closes them right after. Children
+/// spawned with `HandleList` never inherit each other's handles. A child
+/// spawned some other way at the same moment still inherits whatever is
+/// inheritable then, including those duplicates, but only for the duration of
+/// the spawn rather than for however long the parent keeps its pipes.
+///
+/// This calls `CreateProcess` itself, so it can't take a `Command`, some of
+/// whose settings (`env_clear` and `creation_flags`) can't be read back.
+/// Instead it has the same methods for the program, the arguments, the
+/// environment, and the working directory. Streams that aren't set are
+/// inherited from the parent, as with `Stdio::inherit`, unless the child runs
+/// in a [`pty`](#method.pty). The program is found the way `CreateProcess`
+/// finds it, in the parent's directory, the current directory, the system
+/// directories, and then `PATH`, and `.exe` is added if it has no extension.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(windows)]
+/// # fn main() -> std::io::Result<()> {
+/// use std::io::prelude::*;
+///
+/// let (mut reader, writer) = os_pipe::pipe()?;
+/// let (status_reader, status_writer) = os_pipe::pipe()?;
+/// let mut child = os_pipe::HandleList::new("some_child")
+///     .arg("--verbose")
+///     .stdout(&writer)
+///     .pass_pipe("STATUS_PIPE", &status_writer)
+///     .spawn()?;
+/// drop(writer);
+/// drop(status_writer);
+/// # drop(status_reader);
+///
+/// let mut output = String::new();
+/// reader.read_to_string(&mut output)?;
+/// child.wait()?;
+/// # Ok(())
+/// # }
+/// # #[cfg(not(windows))]
+/// # fn main() {}
+/// ```
+///
+/// [`CommandPipeExt::pass_pipe`]: trait.CommandPipeExt.html#tymethod.pass_pipe
+#[derive(Debug)]
+pub struct HandleList<'a> {
+    pub(crate) program: OsString,
+    pub(crate) args: Vec<OsString>,
+    pub(crate) env_clear: bool,
+    pub(crate) envs: Vec<(OsString, Option<OsString>)>,
+    pub(crate) current_dir: Option<PathBuf>,
+    pub(crate) creation_flags: u32,
+    pub(crate) stdin: Option<BorrowedHandle<'a>>,
+    pub(crate) stdout: Option<BorrowedHandle<'a>>,
+    pub(crate) stderr: Option<BorrowedHandle<'a>>,
+    pub(crate) pipes: Vec<(OsString, BorrowedHandle<'a>)>,
+    pub(crate) pty: Option<&'a PtySlave>,
+}
+
+impl<'a> HandleList<'a> {
+    /// A list for running `program` that doesn't pass anything yet. The child
+    /// would inherit only the parent's standard handles, like `Command::new`.
+    pub fn new<S: AsRef<OsStr>>(program: S) -> HandleList<'a> {
+        HandleList {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            env_clear: false,
+            envs: Vec::new(),
+            current_dir: None,
+            creation_flags: 0,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            pipes: Vec::new(),
+            pty: None,
+        }
+    }
+
+    /// Add an argument, like `Command::arg`.
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut HandleList<'a> {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Add several arguments, like `Command::args`.
+    pub fn args<I, S>(&mut self, args: I) -> &mut HandleList<'a>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Set an environment variable in the child, like `Command::env`.
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut HandleList<'a> {
+        let value = Some(value.as_ref().to_owned());
+        self.envs.push((key.as_ref().to_owned(), value));
+        self
+    }
+
+    /// Leave an environment variable out of the child's environment, like
+    /// `Command::env_remove`.
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut HandleList<'a> {
+        self.envs.push((key.as_ref().to_owned(), None));
+        self
+    }
+
+    /// Start the child with an empty environment, apart from variables set
+    /// after this, like `Command::env_clear`. Variables set by
+    /// [`pass_pipe`](#method.pass_pipe) are still there.
+    pub fn env_clear(&mut self) -> &mut HandleList<'a> {
+        self.env_clear = true;
+        self.envs.clear();
+        self
+    }
+
+    /// Set the child's working directory, like `Command::current_dir`.
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut HandleList<'a> {
+        self.current_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Pass extra `CreateProcess` flags, like `CommandExt::creation_flags`.
+    /// `EXTENDED_STARTUPINFO_PRESENT` and `CREATE_UNICODE_ENVIRONMENT` are
+    /// always set.
+    pub fn creation_flags(&mut self, flags: u32) -> &mut HandleList<'a> {
+        self.creation_flags = flags;
+        self
+    }
+
+    /// Give the child `stdin` as its standard input.
+    pub fn stdin<P: AsHandle>(&mut self, stdin: &'a P) -> &mut HandleList<'a> {
+        self.stdin = Some(stdin.as_handle());
+        self
+    }
+
+    /// Give the child `stdout` as its standard output.
+    pub fn stdout<P: AsHandle>(&mut self, stdout: &'a P) -> &mut HandleList<'a> {
+        self.stdout = Some(stdout.as_handle());
+        self
+    }
+
+    /// Give the child `stderr` as its standard error.
+    pub fn stderr<P: AsHandle>(&mut self, stderr: &'a P) -> &mut HandleList<'a> {
+        self.stderr = Some(stderr.as_handle());
+        self
+    }
+
+    /// Run the child in the pseudoconsole `slave`, using
+    /// `PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE`. This is how a
+    /// [`pty`](fn.pty.html) is attached on Windows, in place of
+    /// [`PtySlave::attach`](struct.PtySlave.html#method.attach). Standard
+    /// streams that aren't set go to the console, rather than being
+    /// inherited from the parent.
+    pub fn pty(&mut self, slave: &'a PtySlave) -> &mut HandleList<'a> {
+        self.pty = Some(slave);
+        self
+    }
+
+    /// Let the child inherit `pipe`, and set the environment variable `key`
+    /// to its handle value in the child, like
+    /// [`CommandPipeExt::pass_pipe`](trait.CommandPipeExt.html#tymethod.pass_pipe).
+    /// The child can turn it back into a pipe end with
+    /// [`PipeReader::from_inherited_env`](struct.PipeReader.html#method.from_inherited_env).
+    /// The value is the duplicate's, not `pipe`'s own.
+    pub fn pass_pipe<K: AsRef<OsStr>, P: AsHandle>(
+        &mut self,
+        key: K,
+        pipe: &'a P,
+    ) -> &mut HandleList<'a> {
+        self.pipes.push((key.as_ref().to_owned(), pipe.as_handle()));
+        self
+    }
+
+    /// Spawn the child with `CreateProcess`, letting it inherit only the
+    /// handles in this list. The list can be reused for more children.
+    pub fn spawn(&self) -> io::Result<HandleListChild> {
+        let (handle, id) = sys::spawn_with_handle_list(self)?;
+        Ok(HandleListChild { handle, id })
+    }
+}
+
+/// A child process spawned by [`HandleList::spawn`](struct.HandleList.html#method.spawn).
+///
+/// Like `std::process::Child`, dropping this doesn't wait for the child or
+/// kill it.
+#[derive(Debug)]
+pub struct HandleListChild {
+    handle: OwnedHandle,
+    id: u32,
+}
+
+impl HandleListChild {
+    /// The child's process ID.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Wait for the child to exit, and return its exit status.
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        sys::wait_process(&self.handle, true).map(|status| status.unwrap())
+    }
+
+    /// Return the child's exit status if it has exited, without waiting.
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        sys::wait_process(&self.handle, false)
+    }
+
+    /// Kill the child with `TerminateProcess`. A child that has already
+    /// exited isn't an error.
+    pub fn kill(&mut self) -> io::Result<()> {
+        sys::kill_process(&self.handle)
+    }
+}
+
+impl AsHandle for HandleListChild {
+    fn as_handle(&self) -> BorrowedHandle<'_> {
+        self.handle.as_handle()
+    }
+}
+
+impl AsRawHandle for HandleListChild {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.handle.as_raw_handle()
+    }
+}
+
+impl From<HandleListChild> for OwnedHandle {
+    fn from(child: HandleListChild) -> OwnedHandle {
+        child.handle
+    }
+}
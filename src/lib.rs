@@ -15,6 +15,48 @@
 //! code and no risk of deadlocks. `duct` can run the entire example
 //! below in [one line of code](https://docs.rs/duct/#example).
 //!
+//! # Cargo features
+//!
+//! - `mio`: Implement `mio::event::Source` for `PipeReader`, `PipeWriter`, and
+//!   `Duplex` on Unix, so that they can be registered with a `mio::Poll`. On
+//!   Windows, mio only polls what it opened itself, so instead they convert
+//!   to `mio::windows::NamedPipe` with `TryFrom`, which needs a pipe from
+//!   `PipeOptions::overlapped`.
+//! - `tokio`: Add `AsyncPipeReader` and `AsyncPipeWriter`, which wrap pipe ends
+//!   in `tokio::io::unix::AsyncFd` and implement Tokio's `AsyncRead` and
+//!   `AsyncWrite`. On Windows they wrap Tokio's `NamedPipeClient` instead,
+//!   which needs a pipe from `PipeOptions::overlapped`.
+//! - `futures-io`: Implement `async_io::IoSafe` for `PipeReader`, `PipeWriter`,
+//!   and `Duplex` on Unix, so that wrapping them in `async_io::Async` gives you
+//!   the `futures-io` `AsyncRead` and `AsyncWrite` traits. This works with smol
+//!   and async-std, without pulling in Tokio. It does nothing on other
+//!   platforms. On Windows `async_io::Async` only accepts sockets. Until
+//!   async-io can drive other handles, `blocking::Unblock` is the way to use
+//!   pipes with smol there.
+//! - `io-uring`: Add `PipeRing`, which batches reads and writes on many pipes
+//!   through `io_uring`, for programs that would otherwise spend most of their
+//!   time on one syscall per read. This is Linux-only.
+//!
+//! # Platform support
+//!
+//! Everything here works on Unix and Windows, with a few Unix-only or
+//! Windows-only methods noted in their docs. The crate also builds for WASI
+//! targets like `wasm32-wasip1`, but WASI can't create pipes or spawn children,
+//! so only the parts that work with existing file descriptors are useful there:
+//! reading and writing, the raw fd conversions, and `copy`. `CommandPipeExt`
+//! isn't available, and everything else returns an error of kind `Unsupported`.
+//!
+//! On Redox, the API is the same as on other Unixes, but pipe capacity, `tee`,
+//! `peek`, `as_child_path`, and pseudoterminals are unsupported there, and
+//! `copy`, `splice`, and `send_file` always use ordinary reads and writes.
+//!
+//! On Fuchsia, pipes are zircon sockets created by fdio, and they work with
+//! `Command` as usual. Fuchsia can't hand a child any descriptors beyond its
+//! stdin, stdout, and stderr, so `CommandPipeExt` isn't available. `NamedPipe`
+//! and `Duplex::send_fd` return `Unsupported`, and so do pseudoterminals and
+//! the Linux-only methods. The `tokio` feature does nothing there, since Tokio's
+//! Unix support doesn't build for Fuchsia.
+//!
 //! # Changes
 //!
 //! - 0.8.0
@@ -85,59 +127,503 @@
 //! assert!(output.split_whitespace().eq(vec!["foo", "bar"]));
 //! ```
 
+use std::cmp;
+use std::env;
+use std::ffi::OsStr;
 use std::fs::File;
 use std::io;
+use std::io::prelude::*;
+#[cfg(any(unix, target_os = "wasi"))]
+use std::os::fd::AsFd as AsDescriptor;
+#[cfg(windows)]
+use std::os::windows::io::AsHandle as AsDescriptor;
+#[cfg(all(any(unix, windows), not(target_os = "fuchsia")))]
+use std::path::PathBuf;
+#[cfg(all(any(unix, windows), not(target_os = "fuchsia")))]
+use std::process::Command;
 use std::process::Stdio;
+#[cfg(any(unix, windows))]
+use std::process::{ChildStderr, ChildStdin, ChildStdout};
+use std::time::{Duration, Instant};
 
 /// The reading end of a pipe, returned by [`pipe`](fn.pipe.html).
 ///
+/// Unlike a `File`, a `PipeReader` doesn't expose file-only methods like
+/// `seek` or `metadata`, which fail or give misleading answers on pipes.
 /// `PipeReader` implements `Into<Stdio>`, so you can pass it as an argument to
 /// `Command::stdin` to spawn a child process that reads from the pipe.
+///
+/// Like `File`, `&PipeReader` implements `Read` too, so a reader can be shared
+/// between threads, for example in an `Arc`, without a `Mutex`. Each `read`
+/// is a single syscall, but reads from different threads can interleave.
 #[derive(Debug)]
 pub struct PipeReader(File);
 
 impl PipeReader {
+    /// Create a new `PipeReader` instance that shares the same underlying
+    /// pipe. This uses `dup` on Unix and `DuplicateHandle` on Windows, and the
+    /// new copy is non-inheritable like the original.
     pub fn try_clone(&self) -> io::Result<PipeReader> {
         self.0.try_clone().map(PipeReader)
     }
+
+    /// Close this end of the pipe, like
+    /// [`PipeWriter::close`](struct.PipeWriter.html#method.close).
+    pub fn close(self) -> io::Result<()> {
+        sys::close(self.0)
+    }
+
+    /// Move this pipe end into or out of non-blocking mode. In non-blocking
+    /// mode, a read with no data available returns an error of kind
+    /// `WouldBlock` instead of waiting. This uses `O_NONBLOCK` on Unix and
+    /// `PIPE_NOWAIT` on Windows.
+    ///
+    /// Note that on Unix, this flag is shared with any duplicates of this pipe
+    /// end, including copies given to child processes.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        sys::set_nonblocking(&self.0, nonblocking)
+    }
+
+    /// Let child processes inherit this pipe end, or stop them from doing so.
+    /// This clears or sets `FD_CLOEXEC` on Unix and sets or clears
+    /// `HANDLE_FLAG_INHERIT` on Windows. Pipes start out non-inheritable.
+    ///
+    /// This is for children that expect a pipe somewhere other than
+    /// stdin/stdout/stderr. Make the pipe end inheritable, tell the child
+    /// where to find it with [`raw_value`], and spawn it. Inheritable pipe ends
+    /// go to *every* child spawned while the flag is set, including children
+    /// spawned by other threads, so it's best to turn inheritance back off
+    /// right after spawning (or drop the pipe end, if the parent doesn't need
+    /// it anymore).
+    ///
+    /// Note that on Unix, the child sees an ordinary file descriptor with the
+    /// same number. On Windows, the child sees a handle with the same value,
+    /// but only if it was created with `bInheritHandles`, which
+    /// `std::process::Command` always does.
+    ///
+    /// [`raw_value`]: struct.PipeReader.html#method.raw_value
+    pub fn set_inheritable(&self, inheritable: bool) -> io::Result<()> {
+        sys::set_inheritable(&self.0, inheritable)
+    }
+
+    /// The file descriptor number on Unix or the handle value on Windows,
+    /// suitable for passing to a child on its command line or in its
+    /// environment. The child can turn it back into a pipe end with
+    /// `FromRawFd` or `FromRawHandle`. It's only meaningful in the child if
+    /// [`set_inheritable`] was called first.
+    ///
+    /// [`set_inheritable`]: struct.PipeReader.html#method.set_inheritable
+    pub fn raw_value(&self) -> u64 {
+        sys::raw_value(&self.0)
+    }
+
+    /// Get a path that `command`'s child can open to read from this pipe, for
+    /// programs that only take filenames, like bash's `<(...)`. Pass it as
+    /// an argument.
+    ///
+    /// On Unix this is `/dev/fd/N`, and the pipe is made inheritable in the
+    /// child, as with
+    /// [`CommandPipeExt::pass_pipe`](trait.CommandPipeExt.html#tymethod.pass_pipe).
+    /// Keep the pipe open until the child is spawned. Windows has no such
+    /// path, so instead this creates a named pipe like `\\.\pipe\os_pipe-...`
+    /// and starts a thread that waits for the child to open it and then copies
+    /// this pipe into it. That thread holds a copy of the pipe until the
+    /// copy finishes, so only use the path once, and make sure the child
+    /// actually opens it. Redox returns `Unsupported`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(unix)]
+    /// # fn main() -> std::io::Result<()> {
+    /// use std::io::prelude::*;
+    /// use std::process::{Command, Stdio};
+    ///
+    /// let (reader, mut writer) = os_pipe::pipe()?;
+    /// let mut command = Command::new("cat");
+    /// let path = reader.as_child_path(&mut command)?;
+    /// let child = command.arg(path).stdout(Stdio::piped()).spawn()?;
+    /// drop(reader);
+    ///
+    /// writer.write_all(b"hello")?;
+    /// drop(writer);
+    /// assert_eq!(child.wait_with_output()?.stdout, b"hello");
+    /// # Ok(())
+    /// # }
+    /// # #[cfg(not(unix))]
+    /// # fn main() {}
+    /// ```
+    #[cfg(all(any(unix, windows), not(target_os = "fuchsia")))]
+    pub fn as_child_path(&self, command: &mut Command) -> io::Result<PathBuf> {
+        sys::child_path(&self.0, command, true)
+    }
+
+    /// In a child process, take ownership of a pipe end that the parent
+    /// passed down with
+    /// [`CommandPipeExt::pass_pipe`](trait.CommandPipeExt.html#tymethod.pass_pipe).
+    /// This parses the fd number or handle value from the environment variable
+    /// `key` and then calls [`from_inherited_raw`]. It fails with `NotFound`
+    /// if the variable isn't set and `InvalidData` if it isn't a number.
+    ///
+    /// # Safety
+    ///
+    /// The same as [`from_inherited_raw`]. Nothing checks that the variable
+    /// was set by a parent that actually passed a pipe.
+    ///
+    /// [`from_inherited_raw`]: struct.PipeReader.html#method.from_inherited_raw
+    pub unsafe fn from_inherited_env<K: AsRef<OsStr>>(key: K) -> io::Result<PipeReader> {
+        sys::from_inherited_raw(raw_value_from_env(key.as_ref())?).map(PipeReader)
+    }
+
+    /// In a child process, take ownership of a pipe end with the fd number or
+    /// handle value `raw`, as returned by
+    /// [`raw_value`](struct.PipeReader.html#method.raw_value) in the parent.
+    /// This fails if `raw` isn't open or isn't a pipe, using `fstat` on Unix
+    /// and `GetFileType` on Windows. On success, it makes the pipe end
+    /// non-inheritable again, so that it doesn't leak into the child's own
+    /// children.
+    ///
+    /// # Safety
+    ///
+    /// As with `FromRawFd` and `FromRawHandle`, nothing else may own `raw`.
+    /// The checks above can't tell whether some other part of the program is
+    /// already using it.
+    pub unsafe fn from_inherited_raw(raw: u64) -> io::Result<PipeReader> {
+        sys::from_inherited_raw(raw).map(PipeReader)
+    }
+
+    /// Get the size of the pipe's buffer in bytes. This uses `F_GETPIPE_SZ` on
+    /// Linux and `GetNamedPipeInfo` on Windows. Other platforms return an
+    /// error of kind `Unsupported`.
+    pub fn capacity(&self) -> io::Result<usize> {
+        sys::capacity(&self.0)
+    }
+
+    /// Resize the pipe's buffer, and return the size the OS actually chose,
+    /// which might be larger than requested. Both ends of a pipe share its
+    /// buffer, so this affects the writer too. This uses `F_SETPIPE_SZ` and
+    /// is only supported on Linux. Unprivileged processes are limited to
+    /// `/proc/sys/fs/pipe-max-size`. On Windows, use
+    /// [`pipe_with_capacity`](fn.pipe_with_capacity.html) instead.
+    pub fn set_capacity(&self, capacity: usize) -> io::Result<usize> {
+        sys::set_capacity(&self.0, capacity)
+    }
+
+    /// Move up to `len` bytes from this pipe into `file`, at the file's
+    /// current position, and return the number of bytes moved. On Linux this
+    /// uses `splice`, which doesn't copy the data through userspace. Elsewhere,
+    /// or if `file` doesn't support `splice`, this falls back to an ordinary
+    /// read and write.
+    ///
+    /// If the fallback's write fails, the bytes it already read are lost.
+    /// They're out of the pipe, they may be only partly in `file`, and the
+    /// error doesn't say how many made it. Treat an error here as the end of
+    /// the stream, not as something to retry.
+    pub fn splice_to(&self, file: &File, len: usize) -> io::Result<usize> {
+        sys::splice(&self.0, file, len)
+    }
+
+    /// Wait up to `timeout` for this pipe to become readable, meaning that a
+    /// read would return without blocking, either with data or with EOF.
+    /// Returns `false` if the timeout expires first.
+    ///
+    /// This uses `poll` on Unix. On Windows, anonymous pipes don't support
+    /// any sort of wait, so this polls `PeekNamedPipe` in a sleep loop. To
+    /// wait on more than one pipe at a time, see [`poll`](fn.poll.html).
+    pub fn wait_readable(&self, timeout: Duration) -> io::Result<bool> {
+        poll(&mut [PollEntry::readable(self)], Some(timeout)).map(|n| n > 0)
+    }
+
+    /// Like `read`, but wait no longer than `timeout` for data to arrive. If
+    /// the timeout expires first, this returns an error of kind `TimedOut`.
+    /// See [`wait_readable`](struct.PipeReader.html#method.wait_readable).
+    pub fn read_timeout(&self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        if !self.wait_readable(timeout)? {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out waiting for the pipe to become readable",
+            ));
+        }
+        sys::read(&self.0, buf)
+    }
+
+    /// Get the number of bytes that are buffered in the pipe and ready to read
+    /// without blocking. This uses `FIONREAD` on Unix and `PeekNamedPipe` on
+    /// Windows. After EOF, this is 0.
+    pub fn bytes_available(&self) -> io::Result<usize> {
+        sys::bytes_available(&self.0)
+    }
+
+    /// Copy buffered bytes from the pipe into `buf` without consuming them, and
+    /// return how many were copied. The same bytes are still there for the next
+    /// read. This never blocks, and it returns 0 if nothing's buffered or the
+    /// pipe is at EOF. Use [`wait_readable`] to wait for data first.
+    ///
+    /// This uses `PeekNamedPipe` on Windows. Unix pipes don't support peeking,
+    /// but on Linux this is emulated with `tee` into a scratch pipe. Other Unix
+    /// platforms return an error of kind `Unsupported`.
+    ///
+    /// [`wait_readable`]: struct.PipeReader.html#method.wait_readable
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        sys::peek(&self.0, buf)
+    }
+
+    /// Copy up to `len` bytes from this pipe into `writer` without consuming
+    /// them, and return the number of bytes copied. The same bytes are still
+    /// there for the next read from this pipe. This uses `tee`, and since
+    /// there's no way to emulate that, on platforms other than Linux it
+    /// returns an error of kind `Unsupported`.
+    pub fn tee_into(&self, writer: &PipeWriter, len: usize) -> io::Result<usize> {
+        sys::tee(&self.0, &writer.0, len)
+    }
 }
 
 impl io::Read for PipeReader {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+        sys::read(&self.0, buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        sys::read_vectored(&self.0, bufs)
     }
 }
 
-impl<'a> io::Read for &'a PipeReader {
+impl io::Read for &PipeReader {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut file_ref = &self.0;
-        file_ref.read(buf)
+        sys::read(&self.0, buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        sys::read_vectored(&self.0, bufs)
     }
 }
 
+/// Give the pipe end to a child process as its stdin. This moves the
+/// underlying file descriptor or handle into the `Stdio` without duplicating
+/// it, so the conversion itself doesn't open anything new. To keep using the
+/// pipe in the parent as well, convert a `try_clone` instead.
 impl From<PipeReader> for Stdio {
     fn from(p: PipeReader) -> Stdio {
         p.0.into()
     }
 }
 
+/// Take over the pipe that `Stdio::piped` opened for a child's stdout, to get
+/// methods like `set_nonblocking`, `read_timeout`, and `try_clone` that
+/// `ChildStdout` doesn't have. Take it with `child.stdout.take()`. On Windows,
+/// the standard library opens its end of the pipe for overlapped I/O, as with
+/// [`PipeOptions::overlapped`](struct.PipeOptions.html#method.overlapped), so
+/// don't pass it on to another child.
+#[cfg(any(unix, windows))]
+impl From<ChildStdout> for PipeReader {
+    fn from(stdout: ChildStdout) -> PipeReader {
+        PipeReader(file_from_child_pipe(stdout))
+    }
+}
+
+/// The same as the conversion from `ChildStdout`, for stderr.
+#[cfg(any(unix, windows))]
+impl From<ChildStderr> for PipeReader {
+    fn from(stderr: ChildStderr) -> PipeReader {
+        PipeReader(file_from_child_pipe(stderr))
+    }
+}
+
+#[cfg(unix)]
+fn file_from_child_pipe<T: Into<std::os::unix::io::OwnedFd>>(pipe: T) -> File {
+    File::from(pipe.into())
+}
+
+#[cfg(windows)]
+fn file_from_child_pipe<T: Into<std::os::windows::io::OwnedHandle>>(pipe: T) -> File {
+    File::from(pipe.into())
+}
+
 /// The writing end of a pipe, returned by [`pipe`](fn.pipe.html).
 ///
-/// `PipeWriter` implements `Into<Stdio>`, so you can pass it as an argument to
-/// `Command::stdout` or `Command::stderr` to spawn a child process that writes
-/// to the pipe.
+/// Like [`PipeReader`](struct.PipeReader.html), this doesn't expose file-only
+/// methods. `PipeWriter` implements `Into<Stdio>`, so you can pass it as an
+/// argument to `Command::stdout` or `Command::stderr` to spawn a child process
+/// that writes to the pipe.
+///
+/// `&PipeWriter` implements `Write` too, so a writer can be shared between
+/// threads without a `Mutex`. Writes from different threads can interleave.
+/// To keep each record in one piece, write it with
+/// [`write_atomic`](#method.write_atomic).
 #[derive(Debug)]
 pub struct PipeWriter(File);
 
 impl PipeWriter {
+    /// Create a new `PipeWriter` instance that shares the same underlying
+    /// pipe. This uses `dup` on Unix and `DuplicateHandle` on Windows, and the
+    /// new copy is non-inheritable like the original. This is how you give
+    /// the same pipe to a child as both its stdout and its stderr, as in the
+    /// [crate example](index.html#example).
     pub fn try_clone(&self) -> io::Result<PipeWriter> {
         self.0.try_clone().map(PipeWriter)
     }
+
+    /// Close this end of the pipe, and return any error from `close` on Unix or
+    /// `CloseHandle` on Windows, which dropping it would ignore. Either way,
+    /// it's closed afterwards, and an error isn't worth retrying. Other
+    /// copies from `try_clone` stay open.
+    pub fn close(self) -> io::Result<()> {
+        sys::close(self.0)
+    }
+
+    /// Move this pipe end into or out of non-blocking mode. In non-blocking
+    /// mode, a write to a full pipe returns an error of kind `WouldBlock`
+    /// instead of waiting. See
+    /// [`PipeReader::set_nonblocking`](struct.PipeReader.html#method.set_nonblocking).
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        sys::set_nonblocking(&self.0, nonblocking)
+    }
+
+    /// Let child processes inherit this pipe end, or stop them from doing so.
+    /// See
+    /// [`PipeReader::set_inheritable`](struct.PipeReader.html#method.set_inheritable).
+    pub fn set_inheritable(&self, inheritable: bool) -> io::Result<()> {
+        sys::set_inheritable(&self.0, inheritable)
+    }
+
+    /// The file descriptor number on Unix or the handle value on Windows. See
+    /// [`PipeReader::raw_value`](struct.PipeReader.html#method.raw_value).
+    pub fn raw_value(&self) -> u64 {
+        sys::raw_value(&self.0)
+    }
+
+    /// Get a path that `command`'s child can open to write to this pipe, like
+    /// bash's `>(...)`. See
+    /// [`PipeReader::as_child_path`](struct.PipeReader.html#method.as_child_path).
+    #[cfg(all(any(unix, windows), not(target_os = "fuchsia")))]
+    pub fn as_child_path(&self, command: &mut Command) -> io::Result<PathBuf> {
+        sys::child_path(&self.0, command, false)
+    }
+
+    /// The writing counterpart of
+    /// [`PipeReader::from_inherited_env`](struct.PipeReader.html#method.from_inherited_env).
+    ///
+    /// # Safety
+    ///
+    /// See `PipeReader::from_inherited_env`.
+    pub unsafe fn from_inherited_env<K: AsRef<OsStr>>(key: K) -> io::Result<PipeWriter> {
+        sys::from_inherited_raw(raw_value_from_env(key.as_ref())?).map(PipeWriter)
+    }
+
+    /// The writing counterpart of
+    /// [`PipeReader::from_inherited_raw`](struct.PipeReader.html#method.from_inherited_raw).
+    ///
+    /// # Safety
+    ///
+    /// See `PipeReader::from_inherited_raw`.
+    pub unsafe fn from_inherited_raw(raw: u64) -> io::Result<PipeWriter> {
+        sys::from_inherited_raw(raw).map(PipeWriter)
+    }
+
+    /// Get the size of the pipe's buffer in bytes. See
+    /// [`PipeReader::capacity`](struct.PipeReader.html#method.capacity).
+    pub fn capacity(&self) -> io::Result<usize> {
+        sys::capacity(&self.0)
+    }
+
+    /// Resize the pipe's buffer. See
+    /// [`PipeReader::set_capacity`](struct.PipeReader.html#method.set_capacity).
+    pub fn set_capacity(&self, capacity: usize) -> io::Result<usize> {
+        sys::set_capacity(&self.0, capacity)
+    }
+
+    /// Move up to `len` bytes from `file`, at the file's current position,
+    /// into this pipe, and return the number of bytes moved. See
+    /// [`PipeReader::splice_to`](struct.PipeReader.html#method.splice_to),
+    /// including what an error means for the bytes in flight.
+    pub fn splice_from(&self, file: &File, len: usize) -> io::Result<usize> {
+        sys::splice(file, &self.0, len)
+    }
+
+    /// Write `len` bytes of `file`, starting at `offset`, into this pipe, and
+    /// return the number of bytes written. This is less than `len` only if
+    /// the file ends first. The file's current position doesn't matter and
+    /// doesn't change.
+    ///
+    /// On Linux this uses `sendfile`, so the data never passes through
+    /// userspace, which makes a real difference when feeding a large file to
+    /// a child process. Other platforms fall back to a buffered loop with
+    /// positioned reads. (`sendfile` on macOS and the BSDs and `TransmitFile`
+    /// on Windows only support sending to sockets.) This blocks until it's
+    /// done. It's not meant for non-blocking pipes, where a full pipe fails
+    /// with `WouldBlock` and the count of bytes already written is lost.
+    pub fn send_file(&self, file: &File, offset: u64, len: u64) -> io::Result<u64> {
+        sys::send_file(&self.0, file, offset, len)
+    }
+
+    /// Write all of `buf` in a single write, so that it doesn't interleave
+    /// with writes from other threads or processes sharing the pipe. This is
+    /// what you want when several children log whole records to one pipe.
+    ///
+    /// On Unix, the kernel only makes that promise for writes of up to
+    /// `PIPE_BUF` bytes (4096 on Linux, 512 on macOS and the BSDs), and a
+    /// larger `buf` fails with `InvalidInput` without writing anything. On
+    /// Windows, each write to a blocking pipe goes into the buffer as a unit,
+    /// up to 64 MiB, and a larger `buf` fails the same way. For non-blocking
+    /// pipes on Windows, or to have the reader see the same record
+    /// boundaries, create the pipe with [`PipeOptions::message_mode`]. If the
+    /// pipe accepts only part of `buf`, this fails with `WriteZero`, and the
+    /// record has been cut short.
+    ///
+    /// [`PipeOptions::message_mode`]: struct.PipeOptions.html#method.message_mode
+    pub fn write_atomic(&self, buf: &[u8]) -> io::Result<()> {
+        sys::write_atomic(&self.0, buf)
+    }
+
+    /// Check whether every copy of the reading end has been closed, for
+    /// example because the child reading from this pipe has exited, without
+    /// writing anything. A producer can call this between expensive chunks of
+    /// work, rather than finding out from a `BrokenPipe` error once it's
+    /// done.
+    ///
+    /// On Unix this polls for `POLLERR` or `POLLHUP` with a zero timeout. On
+    /// Windows it calls `PeekNamedPipe`, which fails once the reader is gone.
+    /// A `false` result can be out of date by the time you act on it, so
+    /// writes still need to handle `BrokenPipe`.
+    pub fn is_peer_closed(&self) -> io::Result<bool> {
+        sys::is_peer_closed(&self.0)
+    }
+
+    /// Wait up to `timeout` for this pipe to become writable, meaning that
+    /// there's room for a write or that the reader has gone away. Returns
+    /// `false` if the timeout expires first. This uses `poll` on Unix.
+    /// Windows can't tell whether a pipe has room, so there this always
+    /// returns `true` right away. See
+    /// [`PollEntry::writable`](struct.PollEntry.html#method.writable).
+    pub fn wait_writable(&self, timeout: Duration) -> io::Result<bool> {
+        poll(&mut [PollEntry::writable(self)], Some(timeout)).map(|n| n > 0)
+    }
+
+    /// Like `write_all`, but give up once `timeout` has passed, and return how
+    /// many bytes the pipe accepted. A result shorter than `buf` means the
+    /// reader stopped keeping up, and the rest wasn't written. A reader that
+    /// has gone away still fails with `BrokenPipe`.
+    ///
+    /// On Unix, this waits with `poll` and then writes at most `PIPE_BUF`
+    /// bytes at a time, which is what `poll` guarantees there's room for, so
+    /// none of the writes block. Another thread writing to the same pipe at
+    /// the same moment can still make one block. On Windows, where there's
+    /// no way to wait for room, this switches the pipe to `PIPE_NOWAIT` for
+    /// the duration and retries writes in a sleep loop. The mode belongs to
+    /// the handle, so other threads writing to it in the meantime might see
+    /// `WouldBlock`.
+    pub fn write_timeout(&self, buf: &[u8], timeout: Duration) -> io::Result<usize> {
+        sys::write_timeout(&self.0, buf, timeout)
+    }
 }
 
 impl io::Write for PipeWriter {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+        sys::write(&self.0, buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        sys::write_vectored(&self.0, bufs)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -145,10 +631,13 @@ impl io::Write for PipeWriter {
     }
 }
 
-impl<'a> io::Write for &'a PipeWriter {
+impl io::Write for &PipeWriter {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let mut file_ref = &self.0;
-        file_ref.write(buf)
+        sys::write(&self.0, buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        sys::write_vectored(&self.0, bufs)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -157,106 +646,611 @@ impl<'a> io::Write for &'a PipeWriter {
     }
 }
 
+/// Give the pipe end to a child process as its stdout or stderr. This moves the
+/// underlying file descriptor or handle into the `Stdio` without duplicating
+/// it, so the conversion itself doesn't open anything new. To keep using the
+/// pipe in the parent as well, convert a `try_clone` instead.
 impl From<PipeWriter> for Stdio {
     fn from(p: PipeWriter) -> Stdio {
         p.0.into()
     }
 }
 
+/// Take over the pipe that `Stdio::piped` opened for a child's stdin, like
+/// the conversion from `ChildStdout` to `PipeReader`.
+#[cfg(any(unix, windows))]
+impl From<ChildStdin> for PipeWriter {
+    fn from(stdin: ChildStdin) -> PipeWriter {
+        PipeWriter(file_from_child_pipe(stdin))
+    }
+}
+
 /// Open a new pipe and return a [`PipeReader`] and [`PipeWriter`] pair.
 ///
 /// This corresponds to the `pipe2` library call on Posix and the
 /// `CreatePipe` library call on Windows (though these implementation
 /// details might change). Pipes are non-inheritable, so new child
 /// processes won't receive a copy of them unless they're explicitly
-/// passed as stdin/stdout/stderr.
+/// passed as stdin/stdout/stderr. To change that or other settings, use
+/// [`PipeOptions`].
 ///
 /// [`PipeReader`]: struct.PipeReader.html
 /// [`PipeWriter`]: struct.PipeWriter.html
+/// [`PipeOptions`]: struct.PipeOptions.html
 pub fn pipe() -> io::Result<(PipeReader, PipeWriter)> {
-    sys::pipe()
+    PipeOptions::new().create()
+}
+
+/// Like [`pipe`](fn.pipe.html), but ask for a buffer of `capacity` bytes.
+/// This is shorthand for
+/// [`PipeOptions::capacity`](struct.PipeOptions.html#method.capacity).
+pub fn pipe_with_capacity(capacity: usize) -> io::Result<(PipeReader, PipeWriter)> {
+    PipeOptions::new().capacity(capacity).create()
+}
+
+/// Copy everything from `from` to `to` until EOF, and return the number of
+/// bytes copied.
+///
+/// Unlike `std::io::copy` with a [`PipeReader`] or [`PipeWriter`], this lets
+/// the kernel move the data where it can. On Linux that means
+/// `copy_file_range`, `sendfile`, or `splice`, depending on what the two sides
+/// are. When none of those apply, and on other platforms, it's a buffered
+/// loop. This works with anything that has a file descriptor, not just pipes.
+/// On Windows it takes `AsHandle` instead of `AsFd`, and unlike `std::io::copy`
+/// with a raw `File`, it also works with the overlapped pipes from
+/// [`PipeOptions::overlapped`].
+///
+/// As with `std::io::copy`, the two sides need to be in blocking mode.
+///
+/// [`PipeReader`]: struct.PipeReader.html
+/// [`PipeWriter`]: struct.PipeWriter.html
+/// [`PipeOptions::overlapped`]: struct.PipeOptions.html#method.overlapped
+pub fn copy<R: AsDescriptor, W: AsDescriptor>(from: &R, to: &W) -> io::Result<u64> {
+    sys::copy(from, to)
+}
+
+/// Settings for opening a new pipe, similar to `std::fs::OpenOptions`.
+///
+/// Where the platform allows it, these are applied atomically when the pipe is
+/// created, rather than with a separate call afterwards that could race with
+/// other threads spawning child processes.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// let (reader, writer) = os_pipe::PipeOptions::new()
+///     .nonblocking(true)
+///     .capacity(1 << 16)
+///     .create()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct PipeOptions {
+    nonblocking: bool,
+    capacity: Option<usize>,
+    inheritable: bool,
+    overlapped: bool,
+    message_mode: bool,
+    #[cfg(windows)]
+    security_descriptor: Option<String>,
+}
+
+impl PipeOptions {
+    /// The default settings, which are the same as what [`pipe`](fn.pipe.html)
+    /// uses.
+    pub fn new() -> PipeOptions {
+        PipeOptions {
+            nonblocking: false,
+            capacity: None,
+            inheritable: false,
+            overlapped: false,
+            message_mode: false,
+            #[cfg(windows)]
+            security_descriptor: None,
+        }
+    }
+
+    /// Put both ends of the pipe in non-blocking mode, as with
+    /// [`PipeReader::set_nonblocking`](struct.PipeReader.html#method.set_nonblocking).
+    /// On Unix this is `O_NONBLOCK` in the call to `pipe2`. The default is
+    /// `false`.
+    pub fn nonblocking(&mut self, nonblocking: bool) -> &mut PipeOptions {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    /// Ask for a pipe buffer of at least `capacity` bytes. On Windows this is
+    /// the `nSize` argument to `CreatePipe`. On Linux this sets `F_SETPIPE_SZ`
+    /// right after creating the pipe, and creation fails if the capacity is
+    /// over the limit for an unprivileged process. Other platforms ignore it.
+    /// The default is whatever the OS chooses.
+    pub fn capacity(&mut self, capacity: usize) -> &mut PipeOptions {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Let child processes inherit both ends of the pipe. On Unix this leaves
+    /// out `O_CLOEXEC`, and on Windows it sets `bInheritHandle`. The default
+    /// is `false`, and you usually want to leave it that way. Inheritable pipe
+    /// ends leak into every child, which can keep a pipe from ever reaching
+    /// EOF.
+    ///
+    /// Non-inheritable pipes are created that way atomically, with `pipe2` on
+    /// Linux and the BSDs and with `CreatePipe` on Windows, so another thread
+    /// spawning a child at the same moment can't leak them. macOS has no
+    /// `pipe2`, and neither do Linux kernels before 2.6.27, so there the flag
+    /// is set right after the pipe is created, and there's a brief window
+    /// where that race is possible.
+    pub fn inheritable(&mut self, inheritable: bool) -> &mut PipeOptions {
+        self.inheritable = inheritable;
+        self
+    }
+
+    /// On Windows, build the pipe out of a uniquely named `CreateNamedPipe`
+    /// and `CreateFile` pair opened with `FILE_FLAG_OVERLAPPED`, instead of
+    /// calling `CreatePipe`. This is the same trick the standard library uses
+    /// for its own child pipes. The raw handles then support overlapped I/O,
+    /// including timeouts, `CancelIoEx`, and IOCP registration, while the
+    /// `Read` and `Write` impls keep working as usual. Unix ignores this
+    /// setting. The default is `false`.
+    ///
+    /// Most programs can't do I/O on overlapped handles, so don't pass these
+    /// pipes to a child process as its stdin, stdout, or stderr. Use an
+    /// ordinary pipe for that.
+    pub fn overlapped(&mut self, overlapped: bool) -> &mut PipeOptions {
+        self.overlapped = overlapped;
+        self
+    }
+
+    /// Preserve message boundaries, so that each write is read back as a
+    /// separate message rather than as part of a byte stream. On Linux this is
+    /// a "packet mode" pipe opened with `O_DIRECT`, and on Windows it's a pipe
+    /// with `PIPE_TYPE_MESSAGE` and `PIPE_READMODE_MESSAGE`. Other platforms
+    /// don't have message pipes, and `create` fails with `Unsupported`. The
+    /// default is `false`.
+    ///
+    /// Read with a buffer at least as large as the largest message. On Linux,
+    /// the rest of a message that doesn't fit is discarded. On Windows, it's
+    /// returned by the next read. On Linux, writes larger than `PIPE_BUF`
+    /// (4096 bytes) are split into several messages. On Windows, writes
+    /// larger than 64 MiB fail with `InvalidInput`. Where message pipes are
+    /// unavailable, the portable fallback is to frame the messages yourself,
+    /// for example with a length prefix, over an ordinary pipe.
+    pub fn message_mode(&mut self, message_mode: bool) -> &mut PipeOptions {
+        self.message_mode = message_mode;
+        self
+    }
+
+    /// On Windows, create the pipe with this security descriptor, given as an
+    /// SDDL string like `D:P(A;;GA;;;OW)`, instead of the default one from the
+    /// process token. That covers both `CreatePipe` and the named pipe behind
+    /// [`overlapped`](#method.overlapped) and
+    /// [`message_mode`](#method.message_mode). See
+    /// [`NamedPipe::create_with_security_descriptor`](struct.NamedPipe.html#method.create_with_security_descriptor)
+    /// for more examples. `create` fails with `InvalidInput` if the string
+    /// doesn't parse. This is Windows-only, because anonymous pipes on Unix
+    /// have no name for anyone else to open them by.
+    #[cfg(windows)]
+    pub fn security_descriptor(&mut self, sddl: &str) -> &mut PipeOptions {
+        self.security_descriptor = Some(sddl.to_owned());
+        self
+    }
+
+    /// Open a new pipe with these settings. Options the platform doesn't have
+    /// fail with `Unsupported`.
+    pub fn create(&self) -> io::Result<(PipeReader, PipeWriter)> {
+        sys::pipe(self)
+    }
+}
+
+impl Default for PipeOptions {
+    fn default() -> PipeOptions {
+        PipeOptions::new()
+    }
+}
+
+// Parse the variable set by CommandPipeExt::pass_pipe.
+fn raw_value_from_env(key: &OsStr) -> io::Result<u64> {
+    let value = match env::var_os(key) {
+        Some(value) => value,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "inherited pipe variable not set",
+            ))
+        }
+    };
+    value.to_str().and_then(|v| v.parse().ok()).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "inherited pipe variable isn't a number",
+        )
+    })
+}
+
+// The part of write_atomic() that's the same everywhere, once the size is
+// known to be OK. Retrying on EINTR is safe, because an atomic write that's
+// interrupted hasn't written anything.
+#[cfg_attr(target_os = "wasi", allow(dead_code))]
+fn write_once(file: &File, buf: &[u8]) -> io::Result<()> {
+    loop {
+        match sys::write(file, buf) {
+            Ok(n) if n == buf.len() => return Ok(()),
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "the pipe accepted only part of an atomic write",
+                ))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// The part of write_timeout() that's the same on all the backends that have
+// poll. POLLOUT only promises room for PIPE_BUF bytes, so writes are at most
+// `chunk` bytes, and then they won't block. Nonblocking pipes might still
+// report WouldBlock if another writer got there first.
+#[cfg_attr(any(windows, target_os = "wasi"), allow(dead_code))]
+fn write_polled(file: &File, buf: &[u8], timeout: Duration, chunk: usize) -> io::Result<usize> {
+    let deadline = Instant::now() + timeout;
+    let mut written = 0;
+    while written < buf.len() {
+        let mut entries = [PollEntry {
+            file,
+            writable: true,
+            ready: false,
+        }];
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if sys::poll(&mut entries, Some(remaining))? == 0 {
+            break;
+        }
+        let end = cmp::min(buf.len(), written + chunk);
+        match sys::write(file, &buf[written..end]) {
+            Ok(n) => written += n,
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock
+                    || e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(written)
+}
+
+// The portable fallback for splice(). One read and one write_all, so that
+// the return value has the same meaning. If write_all fails, whatever was
+// read is dropped, and io::Error has nowhere to put the partial count. The
+// splice docs say so.
+fn copy_chunk<R: Read, W: Write>(mut from: R, mut to: W, len: usize) -> io::Result<usize> {
+    let mut buf = vec![0; cmp::min(len, 1 << 16)];
+    let n = from.read(&mut buf)?;
+    to.write_all(&buf[..n])?;
+    Ok(n)
 }
 
 /// Get a duplicated copy of the current process's standard input, as a
 /// [`PipeReader`].
 ///
+/// The duplicate belongs to the caller, and closing it doesn't close the
+/// original. Like the pipes from [`pipe`](fn.pipe.html), it's non-inheritable,
+/// so child processes only get a copy if it's passed to them explicitly.
+///
 /// Reading directly from this pipe isn't recommended, because it's not
 /// synchronized with [`std::io::stdin`]. [`PipeReader`] implements
 /// [`Into<Stdio>`], so it can be passed directly to [`Command::stdin`]. This is
 /// equivalent to [`Stdio::inherit`], though, so it's usually not necessary
 /// unless you need a collection of different pipes.
 ///
+/// This fails with `NotFound` if the process has no standard input, like a
+/// Windows GUI app or a Unix daemon that closed it. See
+/// [`dup_stdin_or_null`](fn.dup_stdin_or_null.html) for a fallback.
+///
 /// [`std::io::stdin`]: https://doc.rust-lang.org/std/io/fn.stdin.html
 /// [`PipeReader`]: struct.PipeReader.html
 /// [`Into<Stdio>`]: https://doc.rust-lang.org/std/process/struct.Stdio.html
 /// [`Command::stdin`]: https://doc.rust-lang.org/std/process/struct.Command.html#method.stdin
 /// [`Stdio::inherit`]: https://doc.rust-lang.org/std/process/struct.Stdio.html#method.inherit
 pub fn dup_stdin() -> io::Result<PipeReader> {
-    sys::dup(io::stdin()).map(PipeReader)
+    dup_std_stream(sys::dup(io::stdin()), "standard input").map(PipeReader)
+}
+
+/// Like [`dup_stdin`](fn.dup_stdin.html), but if the process has no standard
+/// input, return the null device instead, which reads as empty. That's useful
+/// for passing the parent's stdin down to a child, which would otherwise fail
+/// to start or fail later in confusing ways.
+pub fn dup_stdin_or_null() -> io::Result<PipeReader> {
+    match dup_stdin() {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            open_null_device(false).map(PipeReader)
+        }
+        result => result,
+    }
 }
 
 /// Get a duplicated copy of the current process's standard output, as a
 /// [`PipeWriter`](struct.PipeWriter.html).
 ///
+/// The duplicate belongs to the caller, and closing it doesn't close the
+/// original. Like the pipes from [`pipe`](fn.pipe.html), it's non-inheritable,
+/// so child processes only get a copy if it's passed to them explicitly.
+///
 /// Writing directly to this pipe isn't recommended, because it's not
 /// synchronized with [`std::io::stdout`]. [`PipeWriter`] implements
 /// [`Into<Stdio>`], so it can be passed directly to [`Command::stdout`] or
 /// [`Command::stderr`]. This can be useful if you want the child's stderr to go
 /// to the parent's stdout.
 ///
+/// This fails with `NotFound` if the process has no standard output, like a
+/// Windows GUI app or a Unix daemon that closed it. See
+/// [`dup_stdout_or_null`](fn.dup_stdout_or_null.html) for a fallback.
+///
 /// [`std::io::stdout`]: https://doc.rust-lang.org/std/io/fn.stdout.html
 /// [`PipeWriter`]: struct.PipeWriter.html
 /// [`Into<Stdio>`]: https://doc.rust-lang.org/std/process/struct.Stdio.html
 /// [`Command::stdout`]: https://doc.rust-lang.org/std/process/struct.Command.html#method.stdout
 /// [`Command::stderr`]: https://doc.rust-lang.org/std/process/struct.Command.html#method.stderr
-/// [`Stdio::inherit`]: https://doc.rust-lang.org/std/process/struct.Stdio.html#method.inherit
 pub fn dup_stdout() -> io::Result<PipeWriter> {
-    sys::dup(io::stdout()).map(PipeWriter)
+    dup_std_stream(sys::dup(io::stdout()), "standard output").map(PipeWriter)
+}
+
+/// Like [`dup_stdout`](fn.dup_stdout.html), but if the process has no standard
+/// output, return the null device instead, which discards whatever's written to
+/// it. That's useful for passing the parent's stdout down to a child, which
+/// would otherwise fail to start or fail later in confusing ways.
+pub fn dup_stdout_or_null() -> io::Result<PipeWriter> {
+    match dup_stdout() {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => open_null_device(true).map(PipeWriter),
+        result => result,
+    }
 }
 
 /// Get a duplicated copy of the current process's standard error, as a
 /// [`PipeWriter`](struct.PipeWriter.html).
 ///
+/// The duplicate belongs to the caller, and closing it doesn't close the
+/// original. Like the pipes from [`pipe`](fn.pipe.html), it's non-inheritable,
+/// so child processes only get a copy if it's passed to them explicitly.
+///
 /// Writing directly to this pipe isn't recommended, because it's not
 /// synchronized with [`std::io::stderr`]. [`PipeWriter`] implements
 /// [`Into<Stdio>`], so it can be passed directly to [`Command::stdout`] or
 /// [`Command::stderr`]. This can be useful if you want the child's stdout to go
 /// to the parent's stderr.
 ///
+/// This fails with `NotFound` if the process has no standard error, like a
+/// Windows GUI app or a Unix daemon that closed it. See
+/// [`dup_stderr_or_null`](fn.dup_stderr_or_null.html) for a fallback.
+///
 /// [`std::io::stderr`]: https://doc.rust-lang.org/std/io/fn.stderr.html
 /// [`PipeWriter`]: struct.PipeWriter.html
 /// [`Into<Stdio>`]: https://doc.rust-lang.org/std/process/struct.Stdio.html
 /// [`Command::stdout`]: https://doc.rust-lang.org/std/process/struct.Command.html#method.stdout
 /// [`Command::stderr`]: https://doc.rust-lang.org/std/process/struct.Command.html#method.stderr
-/// [`Stdio::inherit`]: https://doc.rust-lang.org/std/process/struct.Stdio.html#method.inherit
 pub fn dup_stderr() -> io::Result<PipeWriter> {
-    sys::dup(io::stderr()).map(PipeWriter)
+    dup_std_stream(sys::dup(io::stderr()), "standard error").map(PipeWriter)
+}
+
+/// Like [`dup_stderr`](fn.dup_stderr.html), but if the process has no standard
+/// error, return the null device instead, which discards whatever's written to
+/// it. That's useful for passing the parent's stderr down to a child, which
+/// would otherwise fail to start or fail later in confusing ways.
+pub fn dup_stderr_or_null() -> io::Result<PipeWriter> {
+    match dup_stderr() {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => open_null_device(true).map(PipeWriter),
+        result => result,
+    }
+}
+
+/// Take ownership of a file descriptor that this process inherited, for
+/// example a pipe that a non-Rust parent left open on fd 5, and turn it into
+/// a `Stdio` for a child process. This marks it non-inheritable first, so
+/// that it only reaches the children it's explicitly given to. To read or
+/// write it directly instead, use
+/// [`PipeReader::from_inherited_raw`](struct.PipeReader.html#method.from_inherited_raw)
+/// or [`PipeWriter::from_inherited_raw`](struct.PipeWriter.html#method.from_inherited_raw),
+/// which also check that it's really a pipe. This is Unix-only. The Windows
+/// version is `stdio_from_raw_handle`.
+///
+/// If this fails, `fd` is closed.
+///
+/// # Safety
+///
+/// `fd` must be open, and nothing else may own it. Once this returns,
+/// dropping the `Stdio`, or the `Command` it's given to, closes it.
+#[cfg(unix)]
+pub unsafe fn stdio_from_raw_fd(fd: std::os::unix::io::RawFd) -> io::Result<Stdio> {
+    use std::os::unix::io::FromRawFd;
+
+    let file = File::from_raw_fd(fd);
+    sys::set_inheritable(&file, false)?;
+    Ok(file.into())
+}
+
+/// Take ownership of a handle that this process inherited, and turn it into a
+/// `Stdio` for a child process. This is the Windows version of
+/// [`stdio_from_raw_fd`](fn.stdio_from_raw_fd.html). It clears
+/// `HANDLE_FLAG_INHERIT` first, so that the handle only reaches the children
+/// it's explicitly given to.
+///
+/// If this fails, `handle` is closed.
+///
+/// # Safety
+///
+/// `handle` must be open, and nothing else may own it. Once this returns,
+/// dropping the `Stdio`, or the `Command` it's given to, closes it.
+#[cfg(windows)]
+pub unsafe fn stdio_from_raw_handle(handle: std::os::windows::io::RawHandle) -> io::Result<Stdio> {
+    use std::os::windows::io::FromRawHandle;
+
+    let file = File::from_raw_handle(handle);
+    sys::set_inheritable(&file, false)?;
+    Ok(file.into())
+}
+
+/// Duplicate any file descriptor you hold, like a socket, a file, or another
+/// pipe, as a [`PipeReader`](struct.PipeReader.html). This is
+/// [`dup_stdin`](fn.dup_stdin.html) for things other than stdin. As with the
+/// other `dup_*` functions, the duplicate is non-inheritable, closing it
+/// doesn't close the original, and it can be passed to a child process as
+/// `Stdio`.
+///
+/// Nothing checks that `source` is really a pipe, so methods like
+/// [`PipeReader::capacity`](struct.PipeReader.html#method.capacity) might
+/// fail. Ordinary reads work with anything that's readable. The Windows
+/// version takes `AsHandle` instead of `AsFd`.
+#[cfg(unix)]
+pub fn dup_reader<T: std::os::unix::io::AsFd>(source: &T) -> io::Result<PipeReader> {
+    sys::dup(source.as_fd()).map(PipeReader)
+}
+
+/// Duplicate any handle you hold, like a socket, a file, or another pipe, as
+/// a [`PipeReader`](struct.PipeReader.html). See the Unix version for more.
+#[cfg(windows)]
+pub fn dup_reader<T: std::os::windows::io::AsHandle>(source: &T) -> io::Result<PipeReader> {
+    sys::dup(source.as_handle()).map(PipeReader)
+}
+
+/// Duplicate any file descriptor you hold as a
+/// [`PipeWriter`](struct.PipeWriter.html). This is the writing counterpart of
+/// [`dup_reader`](fn.dup_reader.html), and the general form of
+/// [`dup_stdout`](fn.dup_stdout.html).
+#[cfg(unix)]
+pub fn dup_writer<T: std::os::unix::io::AsFd>(source: &T) -> io::Result<PipeWriter> {
+    sys::dup(source.as_fd()).map(PipeWriter)
+}
+
+/// Duplicate any handle you hold as a [`PipeWriter`](struct.PipeWriter.html).
+/// See the Unix version for more.
+#[cfg(windows)]
+pub fn dup_writer<T: std::os::windows::io::AsHandle>(source: &T) -> io::Result<PipeWriter> {
+    sys::dup(source.as_handle()).map(PipeWriter)
+}
+
+// A missing standard stream shows up as EBADF or ERROR_INVALID_HANDLE, which
+// makes it look like the caller did something wrong. Say what's going on
+// instead.
+fn dup_std_stream(result: io::Result<File>, name: &str) -> io::Result<File> {
+    result.map_err(|err| {
+        if sys::is_closed_std_stream(&err) {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("the process has no {}", name),
+            )
+        } else {
+            err
+        }
+    })
 }
 
+#[cfg(windows)]
+const NULL_DEVICE: &str = "NUL";
 #[cfg(not(windows))]
+const NULL_DEVICE: &str = "/dev/null";
+
+// Like any File from the standard library, this is non-inheritable.
+fn open_null_device(write: bool) -> io::Result<File> {
+    std::fs::OpenOptions::new()
+        .read(!write)
+        .write(write)
+        .open(NULL_DEVICE)
+}
+
+mod broadcast;
+mod capture;
+mod channel;
+#[cfg(all(any(unix, windows), not(target_os = "fuchsia")))]
+mod command;
+mod duplex;
+mod error;
+#[cfg(windows)]
+mod handle_list;
+mod named_pipe;
+mod pipeline;
+mod poll;
+mod pty;
+mod pump;
+#[cfg(any(unix, windows))]
+mod readiness;
+#[cfg(all(any(unix, windows), not(target_os = "fuchsia"), feature = "tokio"))]
+mod tokio_pipe;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+mod wakeup;
+pub use broadcast::{Broadcaster, ConsumerPolicy};
+pub use capture::{capture_stderr, capture_stdout, CaptureGuard};
+pub use channel::{channel, ChannelReceiver, ChannelSender};
+#[cfg(all(any(unix, windows), not(target_os = "fuchsia")))]
+pub use command::{CommandPipeExt, PipedChild};
+pub use duplex::{duplex, duplex_pipe, Duplex, DuplexPipe};
+pub use error::Error;
+#[cfg(windows)]
+pub use handle_list::{HandleList, HandleListChild};
+pub use named_pipe::NamedPipe;
+#[cfg(windows)]
+pub use named_pipe::NamedPipeClient;
+pub use pipeline::Pipeline;
+pub use poll::{poll, PollEntry};
+pub use pty::{pty, PtyMaster, PtySlave};
+pub use pump::{pump, PumpHandle};
+#[cfg(any(unix, windows))]
+pub use readiness::{Interest, Readiness};
+#[cfg(all(any(unix, windows), not(target_os = "fuchsia"), feature = "tokio"))]
+pub use tokio_pipe::{AsyncPipeReader, AsyncPipeWriter};
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use uring::{Completion, PipeRing};
+pub use wakeup::{wakeup_pipe, Waker, WakeupReader};
+
+#[cfg(all(test, unix, feature = "futures-io"))]
+extern crate async_io;
+#[cfg(all(test, unix, feature = "futures-io"))]
+extern crate futures_lite;
+#[cfg(all(test, unix))]
+extern crate libc;
+#[cfg(all(test, feature = "mio"))]
+extern crate mio;
+#[cfg(all(
+    test,
+    any(unix, windows),
+    not(target_os = "fuchsia"),
+    feature = "tokio"
+))]
+extern crate tokio;
+
+#[cfg(unix)]
 #[path = "unix.rs"]
 mod sys;
+#[cfg(target_os = "wasi")]
+#[path = "wasi.rs"]
+mod sys;
 #[cfg(windows)]
 #[path = "windows.rs"]
 mod sys;
 
-#[cfg(test)]
+// WASI can't open pipes or spawn children, which almost every test needs. On
+// Fuchsia the tests would need the helper binaries packaged into a component.
+#[cfg(all(test, any(unix, windows), not(target_os = "fuchsia")))]
 mod tests {
     use std::env::consts::EXE_EXTENSION;
+    use std::fs::File;
+    use std::io;
     use std::io::prelude::*;
     use std::path::{Path, PathBuf};
     use std::process::Command;
-    use std::sync::{Once, ONCE_INIT};
+    use std::sync::Once;
     use std::thread;
+    use std::time::Duration;
 
     fn path_to_exe(name: &str) -> PathBuf {
         // This project defines some associated binaries for testing, and we shell out to them in
         // these tests. `cargo test` doesn't automatically build associated binaries, so this
         // function takes care of building them explicitly, with the right debug/release flavor.
-        static CARGO_BUILD_ONCE: Once = ONCE_INIT;
+        static CARGO_BUILD_ONCE: Once = Once::new();
         CARGO_BUILD_ONCE.call_once(|| {
             let mut build_command = Command::new("cargo");
-            build_command.args(&["build", "--quiet"]);
+            build_command.args(["build", "--quiet"]);
             if !cfg!(debug_assertions) {
                 build_command.arg("--release");
             }
@@ -305,6 +1299,31 @@ mod tests {
         assert_eq!(out, "some stuff");
     }
 
+    #[test]
+    fn test_shared_writer() {
+        use std::sync::Arc;
+
+        let (mut reader, writer) = ::pipe().unwrap();
+        let writer = Arc::new(writer);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let writer = writer.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        (&*writer).write_all(b"x").unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        drop(writer);
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, vec![b'x'; 400]);
+    }
+
     #[test]
     fn test_pipe_no_data() {
         let (mut reader, writer) = ::pipe().unwrap();
@@ -419,6 +1438,75 @@ mod tests {
         drop(stderr);
     }
 
+    #[test]
+    fn test_missing_parent_handles() {
+        // What dup() reports when the standard stream is closed.
+        #[cfg(unix)]
+        let closed = io::Error::from_raw_os_error(9); // EBADF
+        #[cfg(windows)]
+        let closed = io::Error::from_raw_os_error(6); // ERROR_INVALID_HANDLE
+        let err = ::dup_std_stream(Err(closed), "standard output").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "the process has no standard output");
+
+        // The fallback is only used when something's missing, but the null
+        // device itself should behave.
+        ::dup_stdout_or_null().unwrap();
+        let mut null_reader = ::open_null_device(false).unwrap();
+        assert_eq!(null_reader.read(&mut [0; 1]).unwrap(), 0);
+        let mut null_writer = ::open_null_device(true).unwrap();
+        null_writer.write_all(b"gone").unwrap();
+    }
+
+    #[test]
+    fn test_dup_reader_and_writer() {
+        let path = temp_file_path("dup");
+        let file = File::create(&path).unwrap();
+        let mut writer = ::dup_writer(&file).unwrap();
+        drop(file);
+        writer.write_all(b"foo").unwrap();
+        drop(writer);
+
+        let file = File::open(&path).unwrap();
+        let mut reader = ::dup_reader(&file).unwrap();
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "foo");
+        drop(reader);
+        // The original is still open.
+        let mut file_ref = &file;
+        assert_eq!(file_ref.read(&mut [0]).unwrap(), 0);
+        drop(file);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_dup_writer_file_chunks() {
+        // Files have a position, and each write has to move it along rather
+        // than starting over at the beginning.
+        let path = temp_file_path("dup_chunks");
+        let file = File::create(&path).unwrap();
+        let mut writer = ::dup_writer(&file).unwrap();
+        drop(file);
+        for chunk in &[&b"foo"[..], b"bar", b"baz"] {
+            writer.write_all(chunk).unwrap();
+        }
+        drop(writer);
+
+        let file = File::open(&path).unwrap();
+        let mut reader = ::dup_reader(&file).unwrap();
+        drop(file);
+        let mut chunk = [0; 3];
+        reader.read_exact(&mut chunk).unwrap();
+        assert_eq!(&chunk, b"foo");
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "barbaz");
+        drop(reader);
+        std::fs::remove_file(path).unwrap();
+    }
+
     #[test]
     fn test_try_clone() {
         let (reader, writer) = ::pipe().unwrap();
@@ -433,9 +1521,1553 @@ mod tests {
         assert_eq!(out, "some stuff");
     }
 
+    #[test]
+    fn test_close() {
+        let (mut reader, mut writer) = ::pipe().unwrap();
+        let writer_clone = writer.try_clone().unwrap();
+        writer.write_all(b"some stuff").unwrap();
+        writer.close().unwrap();
+        // The clone is still open, so this doesn't see EOF yet.
+        let mut buf = [0; 10];
+        reader.read_exact(&mut buf).unwrap();
+        writer_clone.close().unwrap();
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "");
+        reader.close().unwrap();
+
+        let (a, b) = ::duplex().unwrap();
+        a.close().unwrap();
+        b.close().unwrap();
+        let (a, b) = ::duplex_pipe().unwrap();
+        a.close().unwrap();
+        b.close().unwrap();
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_large_write() {
+        // Big enough that Windows splits it into three WriteFile calls.
+        let data: Vec<u8> = (0..(2 << 26) + 1).map(|i| (i % 251) as u8).collect();
+        let (mut reader, mut writer) = ::pipe().unwrap();
+        let joiner = thread::spawn(move || {
+            let mut out = Vec::new();
+            reader.read_to_end(&mut out).unwrap();
+            out
+        });
+        writer.write_all(&data).unwrap();
+        drop(writer);
+        assert!(joiner.join().unwrap() == data);
+    }
+
+    #[test]
+    #[ignore]
+    #[cfg(target_pointer_width = "64")]
+    fn test_huge_write() {
+        // Past the 4 GiB that a DWORD can count, which test_large_write is
+        // too small to reach. This takes a while and needs the memory, so
+        // run it with --ignored.
+        let len = (5 << 30) + 1;
+        let data = vec![42u8; len];
+        let (mut reader, mut writer) = ::pipe().unwrap();
+        let joiner = thread::spawn(move || {
+            let mut buf = vec![0; 1 << 20];
+            let mut total = 0;
+            loop {
+                let n = reader.read(&mut buf).unwrap();
+                if n == 0 {
+                    return total;
+                }
+                assert!(buf[..n].iter().all(|&b| b == 42));
+                total += n;
+            }
+        });
+        writer.write_all(&data).unwrap();
+        drop(writer);
+        assert_eq!(joiner.join().unwrap(), len);
+    }
+
+    #[test]
+    fn test_from_child_pipes() {
+        use std::process::Stdio;
+
+        let mut child = Command::new(path_to_exe("cat"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = ::PipeWriter::from(child.stdin.take().unwrap());
+        let stdout = ::PipeReader::from(child.stdout.take().unwrap());
+        let mut stderr = ::PipeReader::from(child.stderr.take().unwrap());
+        stdin.write_all(b"some stuff").unwrap();
+        drop(stdin);
+        let mut out = String::new();
+        stdout
+            .try_clone()
+            .unwrap()
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, "some stuff");
+        let mut err = String::new();
+        stderr.read_to_string(&mut err).unwrap();
+        assert_eq!(err, "");
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_spawn_with_pipes() {
+        use CommandPipeExt;
+
+        let mut command = Command::new(path_to_exe("cat_both"));
+        let mut piped = command.spawn_with_pipes().unwrap();
+        piped.stdin.write_all(b"some stuff").unwrap();
+        drop(piped.stdin);
+        let mut out = String::new();
+        piped.stdout.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "stdout: some stuff");
+        let mut err = String::new();
+        piped.stderr.read_to_string(&mut err).unwrap();
+        assert_eq!(err, "stderr: some stuff");
+        assert!(piped.child.wait().unwrap().success());
+
+        // The command doesn't keep the old pipes, and it can spawn again.
+        let mut piped = command.spawn_with_pipes().unwrap();
+        drop(piped.stdin);
+        let mut out = String::new();
+        piped.stdout.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "stdout: ");
+        assert!(piped.child.wait().unwrap().success());
+        drop(command);
+    }
+
+    #[test]
+    fn test_vectored_io() {
+        let (reader, writer) = ::pipe().unwrap();
+        let bufs = [io::IoSlice::new(b"head"), io::IoSlice::new(b"body")];
+        // writev is atomic for small writes, and Windows gathers the buffers
+        // into one WriteFile.
+        assert_eq!((&writer).write_vectored(&bufs).unwrap(), 8);
+        drop(writer);
+
+        let mut head = [0; 4];
+        let mut body = [0; 16];
+        let mut total = 0;
+        {
+            let mut bufs = [
+                io::IoSliceMut::new(&mut head),
+                io::IoSliceMut::new(&mut body),
+            ];
+            let mut reader_ref = &reader;
+            loop {
+                let n = reader_ref.read_vectored(&mut bufs).unwrap();
+                if n == 0 {
+                    break;
+                }
+                total += n;
+                io::IoSliceMut::advance_slices(&mut &mut bufs[..], n);
+            }
+        }
+        assert_eq!(total, 8);
+        assert_eq!(&head, b"head");
+        assert_eq!(&body[..4], b"body");
+    }
+
+    #[test]
+    fn test_wait_readable() {
+        let (reader, mut writer) = ::pipe().unwrap();
+        assert!(!reader.wait_readable(Duration::from_millis(10)).unwrap());
+        let mut buf = [0; 64];
+        let err = reader
+            .read_timeout(&mut buf, Duration::from_millis(10))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        // Write from another thread after a delay, to make sure the wait
+        // actually wakes up.
+        let joiner = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            writer.write_all(b"x").unwrap();
+            writer
+        });
+        let n = reader
+            .read_timeout(&mut buf, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(&buf[..n], b"x");
+
+        // EOF counts as readable.
+        drop(joiner.join().unwrap());
+        assert!(reader.wait_readable(Duration::from_secs(60)).unwrap());
+        assert_eq!(
+            reader
+                .read_timeout(&mut buf, Duration::from_secs(60))
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_write_timeout() {
+        let (mut reader, writer) = ::pipe().unwrap();
+        assert!(writer.wait_writable(Duration::from_millis(10)).unwrap());
+        // Nothing is reading, so this fills the pipe and then times out.
+        let data = vec![0xff; 1_000_000];
+        let n = writer
+            .write_timeout(&data, Duration::from_millis(10))
+            .unwrap();
+        assert!(n > 0 && n < data.len());
+        if cfg!(unix) {
+            assert!(!writer.wait_writable(Duration::from_millis(10)).unwrap());
+        }
+
+        let joiner = thread::spawn(move || {
+            let mut out = Vec::new();
+            reader.read_to_end(&mut out).unwrap();
+            out.len()
+        });
+        let rest = writer
+            .write_timeout(&data[n..], Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(rest, data.len() - n);
+        drop(writer);
+        assert_eq!(joiner.join().unwrap(), data.len());
+
+        let (reader, writer) = ::pipe().unwrap();
+        drop(reader);
+        let err = writer
+            .write_timeout(b"x", Duration::from_secs(60))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_poll() {
+        let (reader1, _writer1) = ::pipe().unwrap();
+        let (reader2, mut writer2) = ::pipe().unwrap();
+        {
+            let mut entries = [
+                ::PollEntry::readable(&reader1),
+                ::PollEntry::readable(&reader2),
+            ];
+            let n = ::poll(&mut entries, Some(Duration::from_millis(10))).unwrap();
+            assert_eq!(n, 0);
+            assert!(!entries[0].is_ready());
+            assert!(!entries[1].is_ready());
+        }
+
+        writer2.write_all(b"x").unwrap();
+        let mut entries = [
+            ::PollEntry::readable(&reader1),
+            ::PollEntry::readable(&reader2),
+            ::PollEntry::writable(&writer2),
+        ];
+        let n = ::poll(&mut entries, None).unwrap();
+        // A pipe with one byte in it has room for more.
+        assert_eq!(n, 2);
+        assert!(!entries[0].is_ready());
+        assert!(entries[1].is_ready());
+        assert!(entries[2].is_ready());
+    }
+
+    #[test]
+    fn test_wakeup_pipe() {
+        let (wakeup, waker) = ::wakeup_pipe().unwrap();
+        assert_eq!(wakeup.drain().unwrap(), 0);
+        let thread = thread::spawn(move || {
+            // Far more than fits in the pipe. None of these block.
+            for _ in 0..100_000 {
+                waker.notify();
+            }
+            waker
+        });
+        let waker = thread.join().unwrap();
+        let mut entries = [::PollEntry::readable(wakeup.get_ref())];
+        assert_eq!(
+            ::poll(&mut entries, Some(Duration::from_secs(1))).unwrap(),
+            1
+        );
+        assert!(wakeup.drain().unwrap() > 0);
+        assert_eq!(wakeup.drain().unwrap(), 0);
+
+        // After the reader is gone, notifying is a no-op.
+        drop(wakeup);
+        waker.try_clone().unwrap().notify();
+        waker.notify();
+    }
+
+    #[test]
+    fn test_bytes_available_and_peek() {
+        let (mut reader, mut writer) = ::pipe().unwrap();
+        let mut buf = [0; 64];
+        assert_eq!(reader.bytes_available().unwrap(), 0);
+        if cfg!(any(target_os = "linux", windows)) {
+            assert_eq!(reader.peek(&mut buf).unwrap(), 0);
+        }
+
+        writer.write_all(b"some stuff").unwrap();
+        assert_eq!(reader.bytes_available().unwrap(), 10);
+        if cfg!(any(target_os = "linux", windows)) {
+            assert_eq!(reader.peek(&mut buf[..4]).unwrap(), 4);
+            assert_eq!(&buf[..4], b"some");
+            assert_eq!(reader.peek(&mut buf).unwrap(), 10);
+            assert_eq!(&buf[..10], b"some stuff");
+        } else {
+            let err = reader.peek(&mut buf).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        }
+
+        // Peeking shouldn't have consumed anything.
+        assert_eq!(reader.bytes_available().unwrap(), 10);
+        drop(writer);
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "some stuff");
+        assert_eq!(reader.bytes_available().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_pipe_options() {
+        let (mut reader, mut writer) = ::PipeOptions::new()
+            .nonblocking(true)
+            .capacity(1 << 16)
+            .create()
+            .unwrap();
+        let mut buf = [0; 64];
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        if cfg!(target_os = "linux") {
+            assert!(writer.capacity().unwrap() >= 1 << 16);
+        }
+        writer.write_all(b"x").unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_inheritable() {
+        // The child finds the write end by its raw value and writes to it
+        // directly, without going through stdout.
+        let (mut reader, writer) = ::pipe().unwrap();
+        writer.set_inheritable(true).unwrap();
+        let mut child = Command::new(path_to_exe("write_inherited"))
+            .arg(writer.raw_value().to_string())
+            .arg("foo")
+            .spawn()
+            .unwrap();
+        drop(writer);
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert!(child.wait().unwrap().success());
+        assert_eq!(output, "foo");
+    }
+
+    // On Windows, the child could happen to have some unrelated handle with
+    // the same value, so this is only a reliable test on Unix.
+    #[cfg(unix)]
+    #[test]
+    fn test_not_inheritable() {
+        // Turning inheritance back off hides the pipe from the child again.
+        let (_reader, writer) = ::pipe().unwrap();
+        writer.set_inheritable(true).unwrap();
+        writer.set_inheritable(false).unwrap();
+        let status = Command::new(path_to_exe("write_inherited"))
+            .arg(writer.raw_value().to_string())
+            .arg("foo")
+            .stderr(::std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!status.success());
+    }
+
+    #[test]
+    fn test_pass_pipe() {
+        use CommandPipeExt;
+
+        let (mut reader, writer) = ::pipe().unwrap();
+        let mut command = Command::new(path_to_exe("write_inherited"));
+        #[cfg(unix)]
+        command.pass_pipe("OS_PIPE_TEST_PIPE", writer).unwrap();
+        #[cfg(windows)]
+        command.pass_pipe("OS_PIPE_TEST_PIPE", &writer).unwrap();
+        let mut child = command.arg("OS_PIPE_TEST_PIPE").arg("foo").spawn().unwrap();
+        drop(command);
+        #[cfg(windows)]
+        drop(writer);
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert!(child.wait().unwrap().success());
+        assert_eq!(output, "foo");
+    }
+
+    #[test]
+    #[cfg(not(target_os = "redox"))]
+    fn test_as_child_path() {
+        let (input_reader, mut input_writer) = ::pipe().unwrap();
+        let (mut output_reader, output_writer) = ::pipe().unwrap();
+        let mut command = Command::new(path_to_exe("copy_file"));
+        let input_path = input_reader.as_child_path(&mut command).unwrap();
+        let output_path = output_writer.as_child_path(&mut command).unwrap();
+        let mut child = command.arg(input_path).arg(output_path).spawn().unwrap();
+        drop(input_reader);
+        drop(output_writer);
+
+        input_writer.write_all(b"foo").unwrap();
+        drop(input_writer);
+        let mut output = String::new();
+        output_reader.read_to_string(&mut output).unwrap();
+        assert!(child.wait().unwrap().success());
+        assert_eq!(output, "foo");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pass_pipe_as_fd() {
+        use CommandPipeExt;
+
+        // Pass two pipes, to make sure they each land where they should.
+        let (mut reader3, writer3) = ::pipe().unwrap();
+        let (mut reader4, writer4) = ::pipe().unwrap();
+        let mut command = Command::new(path_to_exe("write_inherited"));
+        command.arg("4").arg("foo");
+        command.pass_pipe_as_fd(3, writer3);
+        command.pass_pipe_as_fd(4, writer4);
+        let mut child = command.spawn().unwrap();
+        // The Command owns the writers, and dropping it closes them.
+        drop(command);
+        let mut output = String::new();
+        reader4.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "foo");
+        output.clear();
+        reader3.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "");
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pass_pipe_as_fd_swapped() {
+        use std::os::unix::prelude::*;
+        use CommandPipeExt;
+
+        // Pass each pipe as the other's fd number, so the first dup2 lands on
+        // the second pipe.
+        let (mut reader_a, writer_a) = ::pipe().unwrap();
+        let (mut reader_b, writer_b) = ::pipe().unwrap();
+        let fd_a = writer_a.as_raw_fd();
+        let fd_b = writer_b.as_raw_fd();
+        let mut command = Command::new(path_to_exe("write_inherited"));
+        command.arg(fd_a.to_string()).arg("foo");
+        command.pass_pipe_as_fd(fd_b, writer_a);
+        command.pass_pipe_as_fd(fd_a, writer_b);
+        let mut child = command.spawn().unwrap();
+        drop(command);
+        let mut output = String::new();
+        reader_b.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "foo");
+        output.clear();
+        reader_a.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "");
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_stdio_from_raw() {
+        use std::process::Stdio;
+
+        let (reader, mut writer) = ::pipe().unwrap();
+        // Pretend that this came from a parent, as a bare fd or handle.
+        reader.set_inheritable(true).unwrap();
+        #[cfg(unix)]
+        let stdin = unsafe {
+            use std::os::unix::prelude::*;
+            ::stdio_from_raw_fd(reader.into_raw_fd()).unwrap()
+        };
+        #[cfg(windows)]
+        let stdin = unsafe {
+            use std::os::windows::prelude::*;
+            ::stdio_from_raw_handle(reader.into_raw_handle()).unwrap()
+        };
+        let mut command = Command::new(path_to_exe("cat"));
+        command.stdin(stdin).stdout(Stdio::piped());
+        let child = command.spawn().unwrap();
+        drop(command);
+        writer.write_all(b"foo").unwrap();
+        drop(writer);
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(output.stdout, b"foo");
+    }
+
+    #[test]
+    fn test_from_inherited_raw_not_a_pipe() {
+        let path = temp_file_path("not_a_pipe");
+        let file = File::create(&path).unwrap();
+        let raw = ::sys::raw_value(&file);
+        let err = unsafe { ::PipeReader::from_inherited_raw(raw) }.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_from_inherited_env_missing() {
+        let err = unsafe { ::PipeReader::from_inherited_env("OS_PIPE_TEST_UNSET") }.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_overlapped_pipe() {
+        let (mut reader, mut writer) = ::PipeOptions::new().overlapped(true).create().unwrap();
+        let thread = thread::spawn(move || {
+            writer.write_all(b"foo").unwrap();
+        });
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        thread.join().unwrap();
+        assert_eq!(output, b"foo");
+    }
+
+    #[test]
+    fn test_message_mode() {
+        let result = ::PipeOptions::new().message_mode(true).create();
+        if !cfg!(any(target_os = "linux", target_os = "android", windows)) {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Unsupported);
+            return;
+        }
+        let (mut reader, mut writer) = result.unwrap();
+        writer.write_all(b"foo").unwrap();
+        writer.write_all(b"bar").unwrap();
+        drop(writer);
+        let mut buf = [0; 64];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"foo");
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"bar");
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_message_mode_vectored() {
+        // A vectored write is a single write, so it's a single message.
+        let result = ::PipeOptions::new().message_mode(true).create();
+        if !cfg!(any(target_os = "linux", target_os = "android", windows)) {
+            return;
+        }
+        let (mut reader, mut writer) = result.unwrap();
+        let bufs = [io::IoSlice::new(b"foo"), io::IoSlice::new(b"bar")];
+        assert_eq!(writer.write_vectored(&bufs).unwrap(), 6);
+        writer.write_all(b"baz").unwrap();
+        drop(writer);
+        let mut buf = [0; 64];
+        assert_eq!(reader.read(&mut buf).unwrap(), 6);
+        assert_eq!(&buf[..6], b"foobar");
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"baz");
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_message_mode_too_large() {
+        // Splitting it would break the message boundary, so it fails whole.
+        let (_reader, mut writer) = ::PipeOptions::new().message_mode(true).create().unwrap();
+        let data = vec![0; (1 << 26) + 1];
+        let err = writer.write(&data).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let bufs = [io::IoSlice::new(&data[..1]), io::IoSlice::new(&data[1..])];
+        let err = writer.write_vectored(&bufs).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_channel() {
+        let (mut sender, mut receiver) = ::channel().unwrap();
+        // Make sure messages bigger than the pipe buffer come through whole.
+        let big = vec![42; 1 << 20];
+        let big_clone = big.clone();
+        let thread = thread::spawn(move || {
+            sender.send(b"foo").unwrap();
+            sender.send(b"").unwrap();
+            sender.send(&big_clone).unwrap();
+        });
+        assert_eq!(receiver.recv().unwrap(), Some(b"foo".to_vec()));
+        assert_eq!(receiver.recv().unwrap(), Some(Vec::new()));
+        assert_eq!(receiver.recv().unwrap(), Some(big));
+        thread.join().unwrap();
+        assert_eq!(receiver.recv().unwrap(), None);
+    }
+
+    #[test]
+    fn test_channel_errors() {
+        let (mut sender, mut receiver) = ::channel().unwrap();
+        sender.set_max_message_size(2);
+        let err = sender.send(b"foo").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        sender.set_max_message_size(3);
+        sender.send(b"foo").unwrap();
+        receiver.set_max_message_size(2);
+        let err = receiver.recv().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A truncated message is an error, not a clean EOF.
+        let (mut receiver, mut writer) = {
+            let (reader, writer) = ::pipe().unwrap();
+            (::ChannelReceiver::new(reader), writer)
+        };
+        writer.write_all(&[5, 0, 0, 0, b'x']).unwrap();
+        drop(writer);
+        let err = receiver.recv().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_error() {
+        let (reader, mut writer) = ::pipe().unwrap();
+        reader.set_nonblocking(true).unwrap();
+        let err = (&reader).read(&mut [0; 1]).unwrap_err();
+        assert!(matches!(::Error::from(err), ::Error::WouldBlock(_)));
+        drop(reader);
+        let err = writer.write(b"x").unwrap_err();
+        assert!(matches!(::Error::from(err), ::Error::BrokenPipe(_)));
+
+        // Running out of descriptors is a creation failure, and converting
+        // back and forth leaves the original error alone.
+        #[cfg(unix)]
+        let code = libc::EMFILE;
+        #[cfg(windows)]
+        let code = 4; // ERROR_TOO_MANY_OPEN_FILES
+        let original = io::Error::from_raw_os_error(code);
+        let message = original.to_string();
+        let err = ::Error::from(original);
+        assert!(matches!(err, ::Error::Create(_)));
+        assert_eq!(err.to_string(), message);
+        let err = io::Error::from(err);
+        assert_eq!(err.raw_os_error(), Some(code));
+        assert_eq!(err.to_string(), message);
+        let err = io::Error::from(io::ErrorKind::Unsupported);
+        assert!(matches!(::Error::from(err), ::Error::Unsupported(_)));
+    }
+
+    #[test]
+    fn test_pass_pipe_over_duplex() {
+        let (local, remote) = ::duplex().unwrap();
+        let (mut reader, writer) = ::pipe().unwrap();
+        #[cfg(unix)]
+        let received = {
+            local.send_fd(&writer).unwrap();
+            remote.recv_fd().unwrap()
+        };
+        #[cfg(windows)]
+        let received = unsafe {
+            use std::os::windows::prelude::*;
+            // Send it to ourselves. -1 is the pseudo handle for the current
+            // process.
+            let this_process = BorrowedHandle::borrow_raw(-1isize as RawHandle);
+            local.send_handle(&writer, &this_process).unwrap();
+            remote.recv_handle().unwrap()
+        };
+        drop(writer);
+        let mut received_writer = ::PipeWriter::from(received);
+        received_writer.write_all(b"foo").unwrap();
+        drop(received_writer);
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "foo");
+
+        // Closing the sender is a clean error on the receiving side.
+        drop(local);
+        #[cfg(unix)]
+        let err = remote.recv_fd().unwrap_err();
+        #[cfg(windows)]
+        let err = unsafe { remote.recv_handle() }.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_recv_fd_truncated() {
+        use std::mem;
+        use std::os::raw::c_int;
+        use std::os::unix::prelude::*;
+
+        // send_fd only sends one descriptor, so send more than recv_fd has
+        // room for by hand.
+        let (local, remote) = ::duplex().unwrap();
+        let (_reader, writer) = ::pipe().unwrap();
+        let fds = [writer.as_raw_fd(); 16];
+        let mut data = [0u8; 1];
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut _,
+            iov_len: data.len(),
+        };
+        let fds_len = mem::size_of_val(&fds);
+        let space = unsafe { libc::CMSG_SPACE(fds_len as _) } as usize;
+        let mut control = vec![0u64; space.div_ceil(8)];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut _;
+        msg.msg_controllen = space as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len as _) as _;
+            let dest = libc::CMSG_DATA(cmsg) as *mut c_int;
+            for (i, &fd) in fds.iter().enumerate() {
+                dest.add(i).write_unaligned(fd);
+            }
+            assert_eq!(libc::sendmsg(local.as_raw_fd(), &msg, 0), 1);
+        }
+        let err = remote.recv_fd().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let (input_reader, mut input_writer) = ::pipe().unwrap();
+        let mut first = Command::new(path_to_exe("cat"));
+        first.stdin(input_reader);
+        let (mut children, mut output_reader) = ::Pipeline::new()
+            .command(first)
+            .command(Command::new(path_to_exe("cat")))
+            .command(Command::new(path_to_exe("cat")))
+            .spawn()
+            .unwrap();
+        input_writer.write_all(b"foo").unwrap();
+        drop(input_writer);
+        let mut output = String::new();
+        output_reader.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "foo");
+        assert_eq!(children.len(), 3);
+        for child in &mut children {
+            assert!(child.wait().unwrap().success());
+        }
+    }
+
+    #[test]
+    fn test_pipeline_errors() {
+        let err = ::Pipeline::new().spawn().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let mut first = Command::new(path_to_exe("cat"));
+        first.stdin(::std::process::Stdio::null());
+        let err = ::Pipeline::new()
+            .command(first)
+            .command(Command::new("os_pipe_no_such_command"))
+            .output()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_capture() {
+        // Capturing this process's own stdout would fight with the test
+        // harness, so do it in a child.
+        for stream in ["stdout", "stderr"] {
+            let output = Command::new(path_to_exe("capture"))
+                .arg(stream)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            let (captured, other) = if stream == "stdout" {
+                (output.stdout, output.stderr)
+            } else {
+                (output.stderr, output.stdout)
+            };
+            assert_eq!(captured, b"captured: foobar");
+            assert_eq!(other, b"");
+        }
+    }
+
+    #[test]
+    fn test_pump() {
+        // More than a pipe buffer in each direction, which would deadlock if
+        // the writes and reads happened on one thread.
+        let input = vec![b'x'; 1 << 20];
+        let (stdin_reader, stdin_writer) = ::pipe().unwrap();
+        let mut child = Command::new(path_to_exe("cat"));
+        child.stdin(stdin_reader);
+        let (mut stdout_reader, stdout_writer) = ::pipe().unwrap();
+        child.stdout(stdout_writer);
+        let mut handle = child.spawn().unwrap();
+        drop(child);
+        let pump = ::pump(io::Cursor::new(input.clone()), stdin_writer).unwrap();
+        let mut output = Vec::new();
+        stdout_reader.read_to_end(&mut output).unwrap();
+        assert_eq!(pump.join().unwrap(), input.len() as u64);
+        assert!(handle.wait().unwrap().success());
+        assert_eq!(output, input);
+
+        // Errors come back through join.
+        let (reader, writer) = ::pipe().unwrap();
+        drop(reader);
+        let err = ::pump(io::Cursor::new(input), writer)
+            .unwrap()
+            .join()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_broadcaster() {
+        let input = vec![b'x'; 1 << 20];
+        let (reader, mut writer) = ::pipe().unwrap();
+        let mut broadcaster = ::Broadcaster::new(reader);
+        let mut threads = Vec::new();
+        for _ in 0..3 {
+            let (mut consumer_reader, consumer_writer) = ::pipe().unwrap();
+            broadcaster.writer(consumer_writer);
+            threads.push(thread::spawn(move || {
+                let mut output = Vec::new();
+                consumer_reader.read_to_end(&mut output).unwrap();
+                output
+            }));
+        }
+        let handle = broadcaster.spawn().unwrap();
+        writer.write_all(&input).unwrap();
+        drop(writer);
+        assert_eq!(handle.join().unwrap(), input.len() as u64);
+        for t in threads {
+            assert!(t.join().unwrap() == input);
+        }
+    }
+
+    #[test]
+    fn test_broadcaster_policies() {
+        use ConsumerPolicy;
+
+        let broadcast = |policy, stuck| {
+            let (reader, mut writer) = ::pipe().unwrap();
+            let (mut live_reader, live_writer) = ::pipe().unwrap();
+            let (closed_reader, closed_writer) = ::pipe().unwrap();
+            drop(closed_reader);
+            let mut broadcaster = ::Broadcaster::new(reader);
+            broadcaster
+                .writer(live_writer)
+                .writer(closed_writer)
+                .policy(policy);
+            // A consumer that never reads.
+            let (stuck_reader, stuck_writer) = ::pipe().unwrap();
+            if stuck {
+                broadcaster.writer(stuck_writer);
+            }
+            let handle = broadcaster.spawn().unwrap();
+            let live = thread::spawn(move || {
+                let mut output = Vec::new();
+                live_reader.read_to_end(&mut output).unwrap();
+                output.len()
+            });
+            // More than the stuck consumer's pipe can hold. The broadcaster
+            // might give up early, so ignore write errors.
+            let _ = writer.write_all(&vec![0; 1 << 20]);
+            drop(writer);
+            let result = handle.join();
+            drop(stuck_reader);
+            (result, live.join().unwrap())
+        };
+
+        let (result, _) = broadcast(ConsumerPolicy::Fail, false);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+        let (result, live) = broadcast(ConsumerPolicy::DropClosed, false);
+        assert_eq!(result.unwrap(), 1 << 20);
+        assert_eq!(live, 1 << 20);
+        // The live consumer might fall behind and get dropped too, in which
+        // case the broadcaster stops early. All we know is that the stuck one
+        // didn't hold everything up.
+        let (result, _) = broadcast(ConsumerPolicy::DropSlow, true);
+        result.unwrap();
+    }
+
+    #[test]
+    fn test_copy() {
+        let input = vec![b'x'; 1 << 20];
+        let in_path = temp_file_path("copy_in");
+        let out_path = temp_file_path("copy_out");
+        std::fs::write(&in_path, &input).unwrap();
+
+        // File to pipe, then pipe to file, to exercise both directions.
+        let (reader, writer) = ::pipe().unwrap();
+        let in_file = File::open(&in_path).unwrap();
+        let thread = thread::spawn(move || ::copy(&in_file, &writer).unwrap());
+        let out_file = File::create(&out_path).unwrap();
+        assert_eq!(::copy(&reader, &out_file).unwrap(), input.len() as u64);
+        assert_eq!(thread.join().unwrap(), input.len() as u64);
+        assert_eq!(std::fs::read(&out_path).unwrap(), input);
+        std::fs::remove_file(in_path).unwrap();
+        std::fs::remove_file(out_path).unwrap();
+    }
+
+    #[test]
+    fn test_send_file() {
+        let input: Vec<u8> = (0..1 << 20).map(|i| i as u8).collect();
+        let path = temp_file_path("send_file");
+        std::fs::write(&path, &input).unwrap();
+        let mut file = File::open(&path).unwrap();
+        file.seek(io::SeekFrom::Start(3)).unwrap();
+
+        let (mut reader, writer) = ::pipe().unwrap();
+        let thread = thread::spawn(move || {
+            let first = writer.send_file(&file, 10, 1 << 19).unwrap();
+            // Asking for more than the file has stops at the end.
+            let second = writer.send_file(&file, (1 << 20) - 5, 100).unwrap();
+            // The position is where it was.
+            assert_eq!(file.stream_position().unwrap(), 3);
+            (first, second)
+        });
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(thread.join().unwrap(), (1 << 19, 5));
+        let mut expected = input[10..10 + (1 << 19)].to_vec();
+        expected.extend_from_slice(&input[input.len() - 5..]);
+        assert_eq!(output, expected);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_is_peer_closed() {
+        let (reader, writer) = ::pipe().unwrap();
+        assert!(!writer.is_peer_closed().unwrap());
+        let reader_clone = reader.try_clone().unwrap();
+        drop(reader);
+        assert!(!writer.is_peer_closed().unwrap());
+        drop(reader_clone);
+        assert!(writer.is_peer_closed().unwrap());
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        // 512 is the smallest PIPE_BUF anywhere.
+        const RECORD: usize = 512;
+        let (mut reader, writer) = ::pipe().unwrap();
+        let mut threads = Vec::new();
+        for i in 0..4u8 {
+            let writer = writer.try_clone().unwrap();
+            threads.push(thread::spawn(move || {
+                for _ in 0..100 {
+                    writer.write_atomic(&[i; RECORD]).unwrap();
+                }
+            }));
+        }
+        drop(writer);
+        let mut record = [0; RECORD];
+        let mut count = 0;
+        while count < 400 {
+            reader.read_exact(&mut record).unwrap();
+            assert!(record.iter().all(|&b| b == record[0]), "interleaved");
+            count += 1;
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(reader.read(&mut record).unwrap(), 0);
+
+        #[cfg(unix)]
+        {
+            let (_reader, writer) = ::pipe().unwrap();
+            let err = writer.write_atomic(&vec![0; 1 << 20]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_nonblocking_read() {
+        let (mut reader, mut writer) = ::pipe().unwrap();
+        reader.set_nonblocking(true).unwrap();
+        let mut buf = [0; 64];
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        writer.write_all(b"x").unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        assert_eq!(&buf[..1], b"x");
+        drop(writer);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        // Switching back to blocking mode should work too. There's no writer
+        // anymore, so this read returns EOF rather than blocking.
+        reader.set_nonblocking(false).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_nonblocking_write() {
+        let (reader, mut writer) = ::pipe().unwrap();
+        writer.set_nonblocking(true).unwrap();
+        // Keep writing until the pipe buffer fills up. Pipe buffers are
+        // nowhere near this big on any platform we support.
+        let buf = [0; 4096];
+        let mut total = 0;
+        loop {
+            match writer.write(&buf) {
+                Ok(n) => total += n,
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+                    break;
+                }
+            }
+            assert!(total < 100_000_000, "the pipe never filled up");
+        }
+        assert!(total > 0);
+        drop(reader);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_set_capacity() {
+        let (reader, writer) = ::pipe().unwrap();
+        let size = writer.set_capacity(1 << 17).unwrap();
+        assert!(size >= 1 << 17);
+        assert_eq!(reader.capacity().unwrap(), size);
+        assert_eq!(writer.capacity().unwrap(), size);
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", windows))]
+    fn test_pipe_with_capacity() {
+        let (reader, mut writer) = ::pipe_with_capacity(1 << 17).unwrap();
+        if cfg!(target_os = "linux") {
+            assert!(reader.capacity().unwrap() >= 1 << 17);
+        } else {
+            assert!(reader.capacity().unwrap() > 0);
+        }
+        // Make sure the pipe still works.
+        writer.write_all(b"some stuff").unwrap();
+        drop(writer);
+        let mut out = String::new();
+        (&reader).read_to_string(&mut out).unwrap();
+        assert_eq!(out, "some stuff");
+    }
+
+    fn temp_file_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("os_pipe_test_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_splice() {
+        let in_path = temp_file_path("splice_in");
+        let out_path = temp_file_path("splice_out");
+        std::fs::write(&in_path, b"hello world").unwrap();
+        let in_file = File::open(&in_path).unwrap();
+        let out_file = File::create(&out_path).unwrap();
+
+        let (reader, writer) = ::pipe().unwrap();
+        // A small splice won't fill the pipe buffer, so it won't block.
+        assert_eq!(writer.splice_from(&in_file, 5).unwrap(), 5);
+        assert_eq!(reader.splice_to(&out_file, 100).unwrap(), 5);
+        drop(out_file);
+        assert_eq!(std::fs::read(&out_path).unwrap(), b"hello");
+
+        // The input file's position should have advanced.
+        assert_eq!(writer.splice_from(&in_file, 100).unwrap(), 6);
+        drop(writer);
+        let mut out = String::new();
+        (&reader).read_to_string(&mut out).unwrap();
+        assert_eq!(out, " world");
+
+        std::fs::remove_file(&in_path).unwrap();
+        std::fs::remove_file(&out_path).unwrap();
+    }
+
+    #[test]
+    fn test_tee() {
+        let (mut reader1, mut writer1) = ::pipe().unwrap();
+        let (mut reader2, writer2) = ::pipe().unwrap();
+        writer1.write_all(b"some stuff").unwrap();
+        drop(writer1);
+        let result = reader1.tee_into(&writer2, 100);
+        if cfg!(target_os = "linux") {
+            assert_eq!(result.unwrap(), 10);
+        } else {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Unsupported);
+            return;
+        }
+        drop(writer2);
+        let mut out1 = String::new();
+        reader1.read_to_string(&mut out1).unwrap();
+        let mut out2 = String::new();
+        reader2.read_to_string(&mut out2).unwrap();
+        assert_eq!(out1, "some stuff");
+        assert_eq!(out2, "some stuff");
+    }
+
+    #[test]
+    #[cfg(all(unix, not(target_os = "redox")))]
+    fn test_pty() {
+        let (mut master, slave) = ::pty().unwrap();
+        master.set_window_size(24, 80).unwrap();
+        assert_eq!(master.window_size().unwrap(), (24, 80));
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("test -t 0 && test -t 1 && stty size");
+        let mut child = slave.attach(&mut command).unwrap().spawn().unwrap();
+        // The Command holds copies of the slave too.
+        drop(command);
+        drop(slave);
+        let mut output = String::new();
+        master.read_to_string(&mut output).unwrap();
+        assert!(child.wait().unwrap().success());
+        // The terminal turns the newline into CRLF.
+        assert_eq!(output, "24 80\r\n");
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_pty() {
+        let (mut master, slave) = ::pty().unwrap();
+        assert_eq!(master.window_size().unwrap(), (24, 80));
+        master.set_window_size(30, 100).unwrap();
+        assert_eq!(master.window_size().unwrap(), (30, 100));
+
+        let mut child = ::HandleList::new("cmd")
+            .args(["/c", "echo", "some_stuff"])
+            .pty(&slave)
+            .spawn()
+            .unwrap();
+        // Closing the console can wait for its output to be read.
+        let joiner = thread::spawn(move || {
+            let mut output = Vec::new();
+            master.read_to_end(&mut output).unwrap();
+            output
+        });
+        assert!(child.wait().unwrap().success());
+        drop(slave);
+        let output = joiner.join().unwrap();
+        assert!(String::from_utf8_lossy(&output).contains("some_stuff"));
+    }
+
+    #[test]
+    fn test_duplex() {
+        let (mut a, mut b) = ::duplex().unwrap();
+        // Small writes won't fill the buffer, so they won't block this thread.
+        a.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        b.write_all(b"pong").unwrap();
+        a.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+        drop(b);
+        let mut out = Vec::new();
+        a.read_to_end(&mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_duplex_shutdown() {
+        use std::net::Shutdown;
+
+        let (mut a, mut b) = ::duplex().unwrap();
+        a.write_all(b"request").unwrap();
+        a.shutdown(Shutdown::Write).unwrap();
+        let mut request = String::new();
+        b.read_to_string(&mut request).unwrap();
+        assert_eq!(request, "request");
+
+        // The other direction still works.
+        b.write_all(b"response").unwrap();
+        drop(b);
+        let mut response = String::new();
+        a.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "response");
+    }
+
+    #[test]
+    fn test_duplex_pipe() {
+        let (mut a, mut b) = ::duplex_pipe().unwrap();
+        a.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        (&b).write_all(b"pong").unwrap();
+        (&a).read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // Give one side to a child as stdin and stdout.
+        let (stdin, stdout) = b.into_stdio();
+        let mut command = Command::new(path_to_exe("cat"));
+        let mut child = command.stdin(stdin).stdout(stdout).spawn().unwrap();
+        drop(command);
+        let (mut reader, mut writer) = a.into_parts();
+        writer.write_all(b"through the child").unwrap();
+        drop(writer);
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "through the child");
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_duplex_try_clone() {
+        let (a, b) = ::duplex().unwrap();
+        let mut a_clone = a.try_clone().unwrap();
+        let mut b_ref = &b;
+        a_clone.write_all(b"some stuff").unwrap();
+        drop(a);
+        drop(a_clone);
+        let mut out = String::new();
+        b_ref.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "some stuff");
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "mio"))]
+    fn test_mio_source() {
+        use mio::{Events, Interest, Poll, Token};
+
+        let (mut reader, mut writer) = ::pipe().unwrap();
+        reader.set_nonblocking(true).unwrap();
+        let mut poll = Poll::new().unwrap();
+        poll.registry()
+            .register(&mut reader, Token(42), Interest::READABLE)
+            .unwrap();
+        writer.write_all(b"x").unwrap();
+        let mut events = Events::with_capacity(8);
+        poll.poll(&mut events, None).unwrap();
+        let event = events.iter().next().unwrap();
+        assert_eq!(event.token(), Token(42));
+        assert!(event.is_readable());
+        poll.registry().deregister(&mut reader).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(windows, feature = "mio"))]
+    fn test_mio_named_pipe() {
+        use mio::windows::NamedPipe;
+        use mio::{Events, Interest, Poll, Token};
+        use std::convert::TryFrom;
+
+        let (reader, _writer) = ::pipe().unwrap();
+        let err = NamedPipe::try_from(reader).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let (reader, mut writer) = ::PipeOptions::new().overlapped(true).create().unwrap();
+        let mut reader = NamedPipe::try_from(reader).unwrap();
+        let mut poll = Poll::new().unwrap();
+        poll.registry()
+            .register(&mut reader, Token(42), Interest::READABLE)
+            .unwrap();
+        writer.write_all(b"x").unwrap();
+        let mut events = Events::with_capacity(8);
+        poll.poll(&mut events, None).unwrap();
+        let event = events.iter().next().unwrap();
+        assert_eq!(event.token(), Token(42));
+        assert!(event.is_readable());
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], b'x');
+        poll.registry().deregister(&mut reader).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_readiness() {
+        use std::os::unix::prelude::*;
+        use Interest;
+        use Readiness;
+
+        let epoll = unsafe { OwnedFd::from_raw_fd(libc::epoll_create1(libc::EPOLL_CLOEXEC)) };
+        let wait = || {
+            let mut events = [libc::epoll_event { events: 0, u64: 0 }; 4];
+            let n = unsafe { libc::epoll_wait(epoll.as_raw_fd(), events.as_mut_ptr(), 4, 0) };
+            assert!(n >= 0);
+            let mut tokens: Vec<u64> = events[..n as usize].iter().map(|e| e.u64).collect();
+            tokens.sort();
+            tokens
+        };
+
+        let (reader, writer) = ::pipe().unwrap();
+        reader.register(&epoll, 1, Interest::READABLE).unwrap();
+        writer.register(&epoll, 2, Interest::WRITABLE).unwrap();
+        assert_eq!(wait(), [2]);
+        (&writer).write_all(b"x").unwrap();
+        assert_eq!(wait(), [1, 2]);
+
+        // Registering twice is an error, but changing the token isn't.
+        let err = reader.register(&epoll, 1, Interest::READABLE).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EEXIST));
+        reader
+            .reregister(&epoll, 3, Interest::READABLE | Interest::WRITABLE)
+            .unwrap();
+        writer.deregister(&epoll).unwrap();
+        assert_eq!(wait(), [3]);
+        let err = writer.deregister(&epoll).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    fn test_pipe_ring() {
+        let pipes: Vec<_> = (0..3).map(|_| ::pipe().unwrap()).collect();
+        let mut ring = match ::PipeRing::new(2) {
+            Ok(ring) => ring,
+            // Containers often block io_uring.
+            Err(_) => return,
+        };
+        // Three writes with room for two entries forces an early submit.
+        let mut writes = Vec::new();
+        for (i, (_, writer)) in pipes.iter().enumerate() {
+            writes.push(ring.submit_write(writer, vec![b'a' + i as u8; 3]).unwrap());
+        }
+        let mut reads = Vec::new();
+        for (reader, _) in &pipes {
+            reads.push(ring.submit_read(reader, vec![0; 16]).unwrap());
+        }
+        assert_eq!(ring.in_flight(), 6);
+        let mut done = 0;
+        while let Some(completion) = ring.wait().unwrap() {
+            let n = *completion.result.as_ref().unwrap();
+            assert_eq!(n, 3);
+            if let Some(i) = reads.iter().position(|&t| t == completion.token) {
+                assert_eq!(completion.buf[..n], [b'a' + i as u8; 3]);
+            } else {
+                assert!(writes.contains(&completion.token));
+            }
+            done += 1;
+        }
+        assert_eq!(done, 6);
+        drop(ring);
+
+        // Dropping the ring with a read still in flight cancels it.
+        let (reader, _writer) = pipes.into_iter().next().unwrap();
+        let mut ring = ::PipeRing::new(2).unwrap();
+        ring.submit_read(&reader, vec![0; 16]).unwrap();
+        ring.submit().unwrap();
+        drop(ring);
+    }
+
+    #[test]
+    #[cfg(all(any(unix, windows), not(target_os = "fuchsia"), feature = "tokio"))]
+    fn test_tokio_pipe() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+
+        // Write more than the pipe buffer can hold from another thread, so
+        // that the async reader has to wait for it.
+        let data = vec![0xff; 1_000_000];
+        let data_copy = data.clone();
+        // Windows needs overlapped pipes, and Unix ignores the setting.
+        let (reader, mut writer) = ::PipeOptions::new().overlapped(true).create().unwrap();
+        let mut reader = ::AsyncPipeReader::new(reader).unwrap();
+        let joiner = thread::spawn(move || writer.write_all(&data_copy).unwrap());
+        let mut out = Vec::new();
+        runtime.block_on(reader.read_to_end(&mut out)).unwrap();
+        joiner.join().unwrap();
+        assert_eq!(out, data);
+
+        // And the same thing in the other direction.
+        let (mut reader, writer) = ::PipeOptions::new().overlapped(true).create().unwrap();
+        let mut writer = ::AsyncPipeWriter::new(writer).unwrap();
+        let joiner = thread::spawn(move || {
+            let mut out = Vec::new();
+            reader.read_to_end(&mut out).unwrap();
+            out
+        });
+        runtime.block_on(writer.write_all(&data)).unwrap();
+        drop(writer);
+        assert_eq!(joiner.join().unwrap(), data);
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "futures-io"))]
+    fn test_futures_io_pipe() {
+        use async_io::Async;
+        use futures_lite::future::block_on;
+        use futures_lite::{AsyncReadExt, AsyncWriteExt};
+
+        let data = vec![0xff; 1_000_000];
+        let (reader, writer) = ::pipe().unwrap();
+        let mut reader = Async::new(reader).unwrap();
+        let mut writer = Async::new(writer).unwrap();
+        let data_copy = data.clone();
+        let joiner = thread::spawn(move || {
+            block_on(writer.write_all(&data_copy)).unwrap();
+        });
+        let mut out = Vec::new();
+        block_on(reader.read_to_end(&mut out)).unwrap();
+        joiner.join().unwrap();
+        assert_eq!(out, data);
+    }
+
+    fn named_pipe_path(name: &str) -> PathBuf {
+        let name = format!("os_pipe_test_{}_{}", std::process::id(), name);
+        if cfg!(windows) {
+            Path::new(r"\\.\pipe").join(name)
+        } else {
+            std::env::temp_dir().join(name)
+        }
+    }
+
+    #[test]
+    fn test_named_pipe_server_reads() {
+        let path = named_pipe_path("server_reads");
+        let server = ::NamedPipe::create(&path).unwrap();
+        assert_eq!(server.path(), &*path);
+        let joiner = thread::spawn(move || {
+            let mut writer = ::NamedPipe::open_writer(&path).unwrap();
+            writer.write_all(b"some stuff").unwrap();
+        });
+        let mut reader = server.accept_reader().unwrap();
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        joiner.join().unwrap();
+        assert_eq!(out, "some stuff");
+    }
+
+    #[test]
+    fn test_named_pipe_server_writes() {
+        let path = named_pipe_path("server_writes");
+        let server = ::NamedPipe::create(&path).unwrap();
+        let joiner = thread::spawn(move || {
+            let mut reader = ::NamedPipe::open_reader(&path).unwrap();
+            let mut out = String::new();
+            reader.read_to_string(&mut out).unwrap();
+            out
+        });
+        let mut writer = server.accept_writer().unwrap();
+        writer.write_all(b"some stuff").unwrap();
+        drop(writer);
+        assert_eq!(joiner.join().unwrap(), "some stuff");
+    }
+
+    #[test]
+    fn test_named_pipe_name_taken() {
+        let path = named_pipe_path("name_taken");
+        let _server = ::NamedPipe::create(&path).unwrap();
+        let err = ::NamedPipe::create(&path).unwrap_err();
+        if cfg!(windows) {
+            // Windows reports ERROR_ACCESS_DENIED for a duplicate first instance.
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        } else {
+            assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        }
+    }
+
+    #[test]
+    fn test_named_pipe_removed_on_drop() {
+        let path = named_pipe_path("removed_on_drop");
+        let server = ::NamedPipe::create(&path).unwrap();
+        drop(server);
+        // Now the name should be free again.
+        ::NamedPipe::create(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_handle_list() {
+        let (mut reader, writer) = ::pipe().unwrap();
+        let (mut output_reader, output_writer) = ::pipe().unwrap();
+        // The pipe's variable survives env_clear, and the hidden =X: style
+        // variables are allowed.
+        let mut child = ::HandleList::new(path_to_exe("write_inherited"))
+            .arg("TEST_PIPE")
+            .arg("some stuff with \"quotes\" and spaces")
+            .env_clear()
+            .env("=Z:", "Z:\\")
+            .stdout(&output_writer)
+            .pass_pipe("TEST_PIPE", &writer)
+            .spawn()
+            .unwrap();
+        drop(writer);
+        drop(output_writer);
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "some stuff with \"quotes\" and spaces");
+        // The child's stdout closing shows the duplicates were closed too.
+        let mut output = String::new();
+        output_reader.read_to_string(&mut output).unwrap();
+        assert!(child.wait().unwrap().success());
+        assert!(child.try_wait().unwrap().unwrap().success());
+        child.kill().unwrap();
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_named_pipe_client_wait() {
+        let path = named_pipe_path("client_wait");
+        let err = ::NamedPipeClient::new()
+            .wait(Duration::from_millis(10))
+            .open_reader(&path)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        // Create the server only after the client has started waiting.
+        let server_path = path.clone();
+        let joiner = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let server = ::NamedPipe::create(&server_path).unwrap();
+            let mut writer = server.accept_writer().unwrap();
+            writer.write_all(b"some stuff").unwrap();
+        });
+        let mut reader = ::NamedPipeClient::new()
+            .wait(Duration::from_secs(60))
+            .open_reader(&path)
+            .unwrap();
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        joiner.join().unwrap();
+        assert_eq!(out, "some stuff");
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_security_descriptor() {
+        // Only the owner, which is us.
+        let sddl = "D:P(A;;GA;;;OW)";
+        let path = named_pipe_path("security_descriptor");
+        let server = ::NamedPipe::create_with_security_descriptor(&path, sddl).unwrap();
+        let joiner = thread::spawn(move || {
+            let mut writer = ::NamedPipe::open_writer(&path).unwrap();
+            writer.write_all(b"some stuff").unwrap();
+        });
+        let mut reader = server.accept_reader().unwrap();
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        joiner.join().unwrap();
+        assert_eq!(out, "some stuff");
+
+        for &overlapped in &[false, true] {
+            let (mut reader, mut writer) = ::PipeOptions::new()
+                .overlapped(overlapped)
+                .security_descriptor(sddl)
+                .create()
+                .unwrap();
+            writer.write_all(b"x").unwrap();
+            let mut buf = [0];
+            reader.read_exact(&mut buf).unwrap();
+        }
+
+        let err = ::PipeOptions::new()
+            .security_descriptor("nonsense")
+            .create()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let path = named_pipe_path("bad_security_descriptor");
+        let err = ::NamedPipe::create_with_security_descriptor(&path, "nonsense").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_owned_conversions() {
+        #[cfg(unix)]
+        use std::os::unix::io::OwnedFd as Owned;
+        #[cfg(windows)]
+        use std::os::windows::io::OwnedHandle as Owned;
+
+        let (reader, writer) = ::pipe().unwrap();
+        let reader: ::PipeReader = Owned::from(reader).into();
+        let mut writer: ::PipeWriter = Owned::from(writer).into();
+        writer.write_all(b"some stuff").unwrap();
+        drop(writer);
+        let mut out = String::new();
+        (&reader).read_to_string(&mut out).unwrap();
+        assert_eq!(out, "some stuff");
+    }
+
     #[test]
     fn test_debug() {
         let (reader, writer) = ::pipe().unwrap();
-        format!("{:?} {:?}", reader, writer);
+        let _ = format!("{:?} {:?}", reader, writer);
     }
 }
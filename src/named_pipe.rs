@@ -0,0 +1,175 @@
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(windows)]
+use std::time::Duration;
+
+use sys;
+use PipeReader;
+use PipeWriter;
+
+/// A named pipe that's been created but not yet connected, returned by
+/// [`NamedPipe::create`](struct.NamedPipe.html#method.create).
+///
+/// Unlike the anonymous pipes returned by [`pipe`](fn.pipe.html), a named pipe
+/// lets unrelated processes rendezvous by path. One process creates the pipe
+/// and waits for a peer with [`accept_reader`] or [`accept_writer`]. The other
+/// process connects to it with [`NamedPipe::open_reader`] or
+/// [`NamedPipe::open_writer`]. Both sides end up with an ordinary
+/// [`PipeReader`] or [`PipeWriter`], which can be passed to `Command` like any
+/// other pipe.
+///
+/// On Unix this is a FIFO created with `mkfifo`, and `path` can be any
+/// filesystem path. The FIFO is removed from the filesystem when the
+/// `NamedPipe` is accepted or dropped. On Windows this is a pipe created with
+/// `CreateNamedPipe`, and `path` must look like `\\.\pipe\some_name`. Either
+/// way, each `NamedPipe` connects to exactly one peer.
+///
+/// [`accept_reader`]: struct.NamedPipe.html#method.accept_reader
+/// [`accept_writer`]: struct.NamedPipe.html#method.accept_writer
+/// [`NamedPipe::open_reader`]: struct.NamedPipe.html#method.open_reader
+/// [`NamedPipe::open_writer`]: struct.NamedPipe.html#method.open_writer
+/// [`PipeReader`]: struct.PipeReader.html
+/// [`PipeWriter`]: struct.PipeWriter.html
+#[derive(Debug)]
+pub struct NamedPipe {
+    path: PathBuf,
+    inner: sys::NamedPipe,
+}
+
+impl NamedPipe {
+    /// Create a new named pipe at `path`. This fails with `AlreadyExists` if
+    /// something else already has that name.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<NamedPipe> {
+        let path = path.as_ref().to_owned();
+        let inner = sys::NamedPipe::create(&path)?;
+        Ok(NamedPipe { path, inner })
+    }
+
+    /// Like [`create`](struct.NamedPipe.html#method.create), but with the
+    /// security descriptor given as an SDDL string, instead of the default one
+    /// from the process token. For example, `D:P(A;;GA;;;OW)` allows only the
+    /// pipe's owner, and `D:P(A;;GA;;;OW)(A;;GRGW;;;S-1-5-19)` also lets the
+    /// Local Service account connect. An invalid string fails with
+    /// `InvalidInput`. This is Windows-only. On Unix, set the permissions of
+    /// the directory that holds the FIFO instead.
+    #[cfg(windows)]
+    pub fn create_with_security_descriptor<P: AsRef<Path>>(
+        path: P,
+        sddl: &str,
+    ) -> io::Result<NamedPipe> {
+        let path = path.as_ref().to_owned();
+        let inner = sys::NamedPipe::create_secured(&path, Some(sddl))?;
+        Ok(NamedPipe { path, inner })
+    }
+
+    /// The path that peers can use to open this pipe.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Wait for a peer to open the other end with
+    /// [`open_writer`](struct.NamedPipe.html#method.open_writer), and return
+    /// the reading end. This blocks until the peer arrives.
+    pub fn accept_reader(self) -> io::Result<PipeReader> {
+        self.inner.accept_reader().map(PipeReader)
+    }
+
+    /// Wait for a peer to open the other end with
+    /// [`open_reader`](struct.NamedPipe.html#method.open_reader), and return
+    /// the writing end. This blocks until the peer arrives.
+    pub fn accept_writer(self) -> io::Result<PipeWriter> {
+        self.inner.accept_writer().map(PipeWriter)
+    }
+
+    /// Open the reading end of a named pipe that some other process created.
+    /// On Unix this blocks until the creator calls
+    /// [`accept_writer`](struct.NamedPipe.html#method.accept_writer).
+    pub fn open_reader<P: AsRef<Path>>(path: P) -> io::Result<PipeReader> {
+        sys::open_named_pipe_reader(path.as_ref()).map(PipeReader)
+    }
+
+    /// Open the writing end of a named pipe that some other process created.
+    /// On Unix this blocks until the creator calls
+    /// [`accept_reader`](struct.NamedPipe.html#method.accept_reader).
+    pub fn open_writer<P: AsRef<Path>>(path: P) -> io::Result<PipeWriter> {
+        sys::open_named_pipe_writer(path.as_ref()).map(PipeWriter)
+    }
+}
+
+/// Options for connecting to a Windows named pipe that some other process is
+/// serving, like a service's endpoint. This is Windows-only.
+///
+/// [`NamedPipe::open_reader`](struct.NamedPipe.html#method.open_reader) and
+/// [`open_writer`](struct.NamedPipe.html#method.open_writer) fail right away
+/// if the server isn't there yet or all its instances are busy. This can
+/// wait for it instead, and read in message mode. The path can name a pipe on
+/// another machine, like `\\server\pipe\some_name`, although pipes created
+/// by [`NamedPipe::create`](struct.NamedPipe.html#method.create) only accept
+/// local clients. The result is an ordinary `PipeReader` or `PipeWriter`,
+/// which can be passed to a child process as its stdin or stdout.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(windows)]
+/// # fn main() -> std::io::Result<()> {
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// let reader = os_pipe::NamedPipeClient::new()
+///     .wait(Duration::from_secs(5))
+///     .open_reader(r"\\.\pipe\some_service")?;
+/// Command::new("findstr").arg("ERROR").stdin(reader).status()?;
+/// # Ok(())
+/// # }
+/// # #[cfg(not(windows))]
+/// # fn main() {}
+/// ```
+#[cfg(windows)]
+#[derive(Clone, Debug, Default)]
+pub struct NamedPipeClient {
+    wait: Option<Duration>,
+    message_mode: bool,
+}
+
+#[cfg(windows)]
+impl NamedPipeClient {
+    /// The default settings, which are the same as what
+    /// [`NamedPipe::open_reader`](struct.NamedPipe.html#method.open_reader)
+    /// and [`open_writer`](struct.NamedPipe.html#method.open_writer) do.
+    pub fn new() -> NamedPipeClient {
+        NamedPipeClient::default()
+    }
+
+    /// Keep trying for up to `timeout` while the pipe doesn't exist or all
+    /// its instances are busy, using `WaitNamedPipe` for the latter. If it
+    /// doesn't work out in time, opening fails with `TimedOut`. Other errors,
+    /// like `PermissionDenied`, fail right away. The default is not to wait.
+    pub fn wait(&mut self, timeout: Duration) -> &mut NamedPipeClient {
+        self.wait = Some(timeout);
+        self
+    }
+
+    /// Read the pipe in message mode, so that each read returns one
+    /// message, as with
+    /// [`PipeOptions::message_mode`](struct.PipeOptions.html#method.message_mode).
+    /// This only works if the server created a message pipe, and opening fails
+    /// otherwise. Writers are unaffected, since the server's pipe type decides
+    /// how writes are split. The default is `false`.
+    pub fn message_mode(&mut self, message_mode: bool) -> &mut NamedPipeClient {
+        self.message_mode = message_mode;
+        self
+    }
+
+    /// Open the reading end of the pipe at `path`.
+    pub fn open_reader<P: AsRef<Path>>(&self, path: P) -> io::Result<PipeReader> {
+        sys::open_named_pipe_client(path.as_ref(), false, self.wait, self.message_mode)
+            .map(PipeReader)
+    }
+
+    /// Open the writing end of the pipe at `path`.
+    pub fn open_writer<P: AsRef<Path>>(&self, path: P) -> io::Result<PipeWriter> {
+        sys::open_named_pipe_client(path.as_ref(), true, self.wait, self.message_mode)
+            .map(PipeWriter)
+    }
+}
@@ -0,0 +1,129 @@
+use std::io;
+use std::io::prelude::*;
+use std::process::{Child, Command, ExitStatus};
+
+use PipeReader;
+
+/// A chain of commands like `cmd1 | cmd2 | cmd3` in a shell, with each
+/// command's stdout connected to the next command's stdin.
+///
+/// The first command's stdin and every command's stderr are left however
+/// they were configured on the `Command`. The last command's stdout is a pipe
+/// that the caller reads. Any stdout set on the commands themselves, or stdin
+/// set on the commands after the first, is replaced.
+///
+/// `spawn` takes care of the part that's easy to get wrong by hand, which is
+/// making sure that the parent doesn't hold any copies of the intermediate
+/// pipes. If it did, a command could wait forever for EOF on its stdin.
+///
+/// # Example
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use std::process::Command;
+///
+/// let mut echo = Command::new("echo");
+/// echo.arg("hello world");
+/// let mut tr = Command::new("tr");
+/// tr.arg("a-z").arg("A-Z");
+///
+/// let (output, statuses) = os_pipe::Pipeline::new()
+///     .command(echo)
+///     .command(tr)
+///     .output()?;
+/// assert_eq!(output, b"HELLO WORLD\n");
+/// assert!(statuses.iter().all(|s| s.success()));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    commands: Vec<Command>,
+}
+
+impl Pipeline {
+    /// An empty pipeline.
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    /// Add a command to the end of the pipeline.
+    pub fn command(&mut self, command: Command) -> &mut Pipeline {
+        self.commands.push(command);
+        self
+    }
+
+    /// Start every command, and return the children in order along with the
+    /// read end of the last command's stdout.
+    ///
+    /// Read all the output before waiting on the children, or the last one
+    /// might block on a full pipe and never exit. If one of the commands fails
+    /// to start, the ones already started are killed and waited for before
+    /// the error is returned. An empty pipeline fails with `InvalidInput`.
+    pub fn spawn(&mut self) -> io::Result<(Vec<Child>, PipeReader)> {
+        if self.commands.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a pipeline needs at least one command",
+            ));
+        }
+        let mut children = Vec::new();
+        let mut previous_reader = None;
+        for mut command in self.commands.drain(..) {
+            let (reader, writer) = match ::pipe() {
+                Ok(pipe) => pipe,
+                Err(e) => return Err(abort(children, e)),
+            };
+            if let Some(previous_reader) = previous_reader.take() {
+                command.stdin(previous_reader);
+            }
+            command.stdout(writer);
+            let result = command.spawn();
+            // The Command owns the parent's copies of this command's stdin
+            // and stdout. Close them now, so that only the children have
+            // them.
+            drop(command);
+            match result {
+                Ok(child) => children.push(child),
+                Err(e) => return Err(abort(children, e)),
+            }
+            previous_reader = Some(reader);
+        }
+        Ok((children, previous_reader.unwrap()))
+    }
+
+    /// Start every command, read all of the last command's output, and then
+    /// wait for all of them. This returns the output and each command's exit
+    /// status, in order.
+    pub fn output(&mut self) -> io::Result<(Vec<u8>, Vec<ExitStatus>)> {
+        let (mut children, mut reader) = self.spawn()?;
+        let mut output = Vec::new();
+        let read_result = reader.read_to_end(&mut output);
+        // Wait for every child even if reading or another wait failed, so that
+        // none are left as zombies, and then return the first error.
+        drop(reader);
+        let mut statuses = Vec::new();
+        let mut first_error = read_result.err();
+        for child in &mut children {
+            match child.wait() {
+                Ok(status) => statuses.push(status),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok((output, statuses)),
+        }
+    }
+}
+
+// Clean up after a failed spawn, and return the error.
+fn abort(children: Vec<Child>, err: io::Error) -> io::Error {
+    for mut child in children {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    err
+}
@@ -0,0 +1,82 @@
+use std::fs::File;
+use std::io;
+use std::time::Duration;
+
+use sys;
+use PipeReader;
+use PipeWriter;
+
+/// A pipe end to wait on with [`poll`](fn.poll.html), along with whether it
+/// turned out to be ready.
+#[derive(Debug)]
+// WASI can't poll, so it never looks at these.
+#[cfg_attr(target_os = "wasi", allow(dead_code))]
+pub struct PollEntry<'a> {
+    pub(crate) file: &'a File,
+    pub(crate) writable: bool,
+    pub(crate) ready: bool,
+}
+
+impl<'a> PollEntry<'a> {
+    /// Wait for `reader` to become readable, meaning that a read would return
+    /// without blocking, either with data or with EOF.
+    pub fn readable(reader: &'a PipeReader) -> PollEntry<'a> {
+        PollEntry {
+            file: &reader.0,
+            writable: false,
+            ready: false,
+        }
+    }
+
+    /// Wait for `writer` to become writable, meaning that there's room in the
+    /// pipe or that the reader has gone away. Windows can't tell whether a
+    /// pipe has room, so there writers always count as ready.
+    pub fn writable(writer: &'a PipeWriter) -> PollEntry<'a> {
+        PollEntry {
+            file: &writer.0,
+            writable: true,
+            ready: false,
+        }
+    }
+
+    /// Whether this pipe end was ready when the last call to
+    /// [`poll`](fn.poll.html) returned.
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+}
+
+/// Wait for any of several pipe ends to become ready, and return how many of
+/// them are. Check which ones with
+/// [`PollEntry::is_ready`](struct.PollEntry.html#method.is_ready). If
+/// `timeout` is `None`, this waits forever, and otherwise it returns 0 when the
+/// timeout expires.
+///
+/// This is the `poll` library call on Unix. On Windows, anonymous pipes don't
+/// support any sort of wait, so this polls `PeekNamedPipe` in a sleep loop.
+/// That means that on Windows this adds some latency, but it still saves you
+/// from needing one thread per pipe.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// use os_pipe::{pipe, poll, PollEntry};
+/// use std::io::prelude::*;
+///
+/// let (stdout_reader, mut stdout_writer) = pipe()?;
+/// let (stderr_reader, mut stderr_writer) = pipe()?;
+/// stderr_writer.write_all(b"oops")?;
+///
+/// let mut entries = [
+///     PollEntry::readable(&stdout_reader),
+///     PollEntry::readable(&stderr_reader),
+/// ];
+/// assert_eq!(poll(&mut entries, None)?, 1);
+/// assert!(entries[1].is_ready());
+/// # Ok(())
+/// # }
+/// ```
+pub fn poll(entries: &mut [PollEntry], timeout: Option<Duration>) -> io::Result<usize> {
+    sys::poll(entries, timeout)
+}
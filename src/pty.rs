@@ -0,0 +1,157 @@
+use std::io;
+use std::process::Command;
+#[cfg(not(windows))]
+use std::process::Stdio;
+
+use sys;
+
+/// The controlling side of a pseudoterminal, returned by [`pty`](fn.pty.html).
+///
+/// Reading from the master gets whatever the child writes to its terminal,
+/// and writing to it is like typing. The terminal does its usual processing in
+/// between, so for example a newline the child writes comes back as `\r\n`,
+/// and what you write is echoed. Once every copy of the slave is closed,
+/// reads return EOF. (Linux reports that as `EIO`, which this converts.) On
+/// Windows, the slave is the console itself, and closing its last copy closes
+/// the console, even for a child that's still running in it.
+#[derive(Debug)]
+pub struct PtyMaster(pub(crate) sys::PtyMasterInner);
+
+impl PtyMaster {
+    /// Create a new `PtyMaster` instance that shares the same underlying
+    /// terminal, like
+    /// [`PipeReader::try_clone`](struct.PipeReader.html#method.try_clone).
+    pub fn try_clone(&self) -> io::Result<PtyMaster> {
+        self.0.try_clone().map(PtyMaster)
+    }
+
+    /// Get the terminal's size as `(rows, columns)`. A new pty has a size of
+    /// `(0, 0)` until one is set, except on Windows, where ConPTY needs a size
+    /// from the start, and it's `(24, 80)`.
+    pub fn window_size(&self) -> io::Result<(u16, u16)> {
+        sys::window_size(&self.0)
+    }
+
+    /// Set the terminal's size, using `TIOCSWINSZ`. The kernel sends
+    /// `SIGWINCH` to the child when this changes the size, and programs like
+    /// editors and pagers redraw to fit. On Windows this is
+    /// `ResizePseudoConsole`, which fails once the slave is closed.
+    pub fn set_window_size(&self, rows: u16, cols: u16) -> io::Result<()> {
+        sys::set_window_size(&self.0, rows, cols)
+    }
+}
+
+impl io::Read for PtyMaster {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        sys::read_pty(&self.0, buf)
+    }
+}
+
+impl io::Read for &PtyMaster {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        sys::read_pty(&self.0, buf)
+    }
+}
+
+impl io::Write for PtyMaster {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        sys::write_pty(&self.0, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Write for &PtyMaster {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        sys::write_pty(&self.0, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The terminal side of a pseudoterminal, returned by [`pty`](fn.pty.html).
+///
+/// This is what the child sees as its terminal. The easiest way to give it to
+/// a child is [`attach`](#method.attach). It also implements `Into<Stdio>`,
+/// like a pipe end, but a child that only gets it that way won't have a
+/// controlling terminal, so for example Ctrl-C won't reach it as `SIGINT`.
+///
+/// On Windows, this is the pseudoconsole, and children are attached to it
+/// with [`HandleList::pty`](struct.HandleList.html#method.pty) instead. The
+/// console closes when the last copy of this is dropped, so keep it until
+/// the child has exited. Closing it can wait for the master to read the
+/// console's remaining output, so don't do it on the thread that reads.
+#[derive(Debug)]
+pub struct PtySlave(pub(crate) sys::PtySlaveInner);
+
+impl PtySlave {
+    /// Create a new `PtySlave` instance that shares the same underlying
+    /// terminal, like
+    /// [`PipeReader::try_clone`](struct.PipeReader.html#method.try_clone).
+    pub fn try_clone(&self) -> io::Result<PtySlave> {
+        self.0.try_clone().map(PtySlave)
+    }
+
+    /// Run `command`'s child in this terminal: give it the slave as its
+    /// stdin, stdout, and stderr, and make it the leader of a new session
+    /// with the slave as its controlling terminal. Once the child is
+    /// spawned, drop both the parent's slave and the `Command`, which holds
+    /// copies of it, so that the master sees EOF when the child exits. This
+    /// returns `Unsupported` on Windows.
+    pub fn attach<'a>(&self, command: &'a mut Command) -> io::Result<&'a mut Command> {
+        sys::attach_pty(command, &self.0)?;
+        Ok(command)
+    }
+}
+
+/// Like the conversions for `PipeReader` and `PipeWriter`, this moves the
+/// terminal into the `Stdio` without duplicating it.
+#[cfg(not(windows))]
+impl From<PtySlave> for Stdio {
+    fn from(s: PtySlave) -> Stdio {
+        s.0.into()
+    }
+}
+
+/// Open a new pseudoterminal and return both sides, for running programs that
+/// behave differently when they're not attached to a terminal, like `git`,
+/// `ssh`, or anything that colors its output.
+///
+/// This is `openpty` on Unix. Like [`pipe`](fn.pipe.html), both sides are
+/// non-inheritable, but since `openpty` has no flag for that, there's a brief
+/// window where another thread spawning a child could leak them. On Windows
+/// this is `CreatePseudoConsole`, which needs Windows 10 1809 or later, and
+/// older versions return `Unsupported`. A ConPTY is attached to a child with
+/// a startup attribute rather than through its standard handles, so the
+/// child has to be spawned with
+/// [`HandleList::pty`](struct.HandleList.html#method.pty). Other platforms
+/// without `openpty` return `Unsupported` too.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(unix)]
+/// # fn main() -> std::io::Result<()> {
+/// use std::io::prelude::*;
+/// use std::process::Command;
+///
+/// let (mut master, slave) = os_pipe::pty()?;
+/// let mut child = slave.attach(Command::new("tty").arg("-s"))?.spawn()?;
+/// drop(slave);
+///
+/// let mut output = Vec::new();
+/// master.read_to_end(&mut output)?;
+/// assert!(child.wait()?.success());
+/// # Ok(())
+/// # }
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+pub fn pty() -> io::Result<(PtyMaster, PtySlave)> {
+    let (master, slave) = sys::openpty()?;
+    Ok((PtyMaster(master), PtySlave(slave)))
+}
@@ -0,0 +1,69 @@
+use std::io;
+use std::io::prelude::*;
+use std::panic;
+use std::thread;
+
+/// A running copy from [`pump`](fn.pump.html) or
+/// [`Broadcaster::spawn`](struct.Broadcaster.html#method.spawn). Call
+/// [`join`](struct.PumpHandle.html#method.join) to wait for it to finish.
+///
+/// Dropping a `PumpHandle` doesn't stop the copy. The thread keeps running in
+/// the background until it reaches EOF or an error.
+#[derive(Debug)]
+pub struct PumpHandle {
+    pub(crate) thread: thread::JoinHandle<io::Result<u64>>,
+}
+
+impl PumpHandle {
+    /// Wait for the copy to finish, and return the number of bytes copied or
+    /// the first error. If the reader or writer panicked, this panics too.
+    pub fn join(self) -> io::Result<u64> {
+        match self.thread.join() {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+/// Spawn a thread that copies everything from `reader` to `writer` until EOF,
+/// and then drops both of them.
+///
+/// This is the usual way to feed a child's stdin while reading its stdout on
+/// the current thread. Doing both on one thread risks deadlock: if the child
+/// fills its stdout pipe while the parent is blocked writing to its stdin,
+/// neither side can make progress. Dropping `writer` at the end closes it, so
+/// the child sees EOF on its stdin. (That only works if no other copies of
+/// the writer are open, including the one held by the `Command`.)
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// use std::io::prelude::*;
+///
+/// let (mut reader, writer) = os_pipe::pipe()?;
+/// let input: &[u8] = b"some input";
+/// let handle = os_pipe::pump(input, writer)?;
+///
+/// let mut output = Vec::new();
+/// reader.read_to_end(&mut output)?;
+/// assert_eq!(handle.join()?, output.len() as u64);
+/// # Ok(())
+/// # }
+/// ```
+pub fn pump<R, W>(reader: R, writer: W) -> io::Result<PumpHandle>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let thread = thread::Builder::new()
+        .name("os_pipe pump".into())
+        .spawn(move || {
+            let mut reader = reader;
+            let mut writer = writer;
+            let n = io::copy(&mut reader, &mut writer)?;
+            writer.flush()?;
+            Ok(n)
+        })?;
+    Ok(PumpHandle { thread })
+}
@@ -0,0 +1,183 @@
+#[cfg(unix)]
+use std::fs::File;
+use std::io;
+use std::ops::BitOr;
+#[cfg(unix)]
+use std::os::unix::prelude::*;
+#[cfg(windows)]
+use std::os::windows::prelude::*;
+
+use sys;
+use Duplex;
+use PipeReader;
+use PipeWriter;
+
+/// The events to register a pipe end for with
+/// [`Readiness::register`](trait.Readiness.html#tymethod.register). Combine
+/// them with `|`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interest {
+    readable: bool,
+    writable: bool,
+}
+
+impl Interest {
+    /// Readable, meaning that a read would return data or EOF.
+    pub const READABLE: Interest = Interest {
+        readable: true,
+        writable: false,
+    };
+
+    /// Writable, meaning that there's room in the pipe or the reader has gone
+    /// away.
+    pub const WRITABLE: Interest = Interest {
+        readable: false,
+        writable: true,
+    };
+
+    /// Whether this includes `READABLE`.
+    pub fn is_readable(self) -> bool {
+        self.readable
+    }
+
+    /// Whether this includes `WRITABLE`.
+    pub fn is_writable(self) -> bool {
+        self.writable
+    }
+}
+
+impl BitOr for Interest {
+    type Output = Interest;
+
+    fn bitor(self, other: Interest) -> Interest {
+        Interest {
+            readable: self.readable || other.readable,
+            writable: self.writable || other.writable,
+        }
+    }
+}
+
+// What sys::register should do with the fd. Backends without epoll or kqueue
+// don't look inside.
+#[cfg(unix)]
+#[derive(Clone, Copy, Debug)]
+#[allow(dead_code)]
+pub(crate) enum Registration {
+    Add(u64, Interest),
+    Modify(u64, Interest),
+    Delete,
+}
+
+/// Hooks for adding pipe ends to an event loop you run yourself, without going
+/// through mio or Tokio.
+///
+/// On Unix, this talks to an epoll instance on Linux and Android, or a kqueue
+/// on macOS and the BSDs, which you create and wait on however you like. The
+/// `token` comes back in the event's `data` field (epoll) or `udata` field
+/// (kqueue). Registrations are level-triggered. Other Unixes return
+/// `Unsupported`.
+///
+/// On Windows, pipes don't have readiness, so instead this associates a pipe
+/// end with an I/O completion port, after which the completions of your own
+/// overlapped `ReadFile` and `WriteFile` calls on its raw handle arrive at the
+/// port with `key`. That needs a pipe created with
+/// [`PipeOptions::overlapped`](struct.PipeOptions.html#method.overlapped).
+/// The `Read` and `Write` impls of an associated pipe keep working, and their
+/// internal operations don't post anything to the port.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(target_os = "linux")]
+/// # fn main() -> std::io::Result<()> {
+/// # extern crate libc;
+/// use os_pipe::{Interest, Readiness};
+/// use std::io::prelude::*;
+/// use std::os::unix::prelude::*;
+///
+/// let epoll = unsafe { OwnedFd::from_raw_fd(libc::epoll_create1(libc::EPOLL_CLOEXEC)) };
+/// let (reader, mut writer) = os_pipe::pipe()?;
+/// reader.register(&epoll, 42, Interest::READABLE)?;
+/// writer.write_all(b"x")?;
+///
+/// let mut events = [libc::epoll_event { events: 0, u64: 0 }; 8];
+/// let n = unsafe { libc::epoll_wait(epoll.as_raw_fd(), events.as_mut_ptr(), 8, -1) };
+/// assert_eq!(n, 1);
+/// let token = events[0].u64;
+/// assert_eq!(token, 42);
+/// # Ok(())
+/// # }
+/// # #[cfg(not(target_os = "linux"))]
+/// # fn main() {}
+/// ```
+pub trait Readiness {
+    /// Add this pipe end to the epoll or kqueue instance `poller`, with
+    /// `token` to identify it in events. Adding it twice fails with `EEXIST`.
+    /// This is Unix-only.
+    #[cfg(unix)]
+    fn register<P: AsFd>(&self, poller: &P, token: u64, interest: Interest) -> io::Result<()>;
+
+    /// Change the token or interest of a pipe end that's already registered
+    /// with `poller`. This fails with `ENOENT` if it isn't registered. This is
+    /// Unix-only.
+    #[cfg(unix)]
+    fn reregister<P: AsFd>(&self, poller: &P, token: u64, interest: Interest) -> io::Result<()>;
+
+    /// Remove this pipe end from `poller`, or fail with `ENOENT` if it isn't
+    /// registered. Closing the pipe end does the same thing, as long as it's
+    /// the last copy. This is Unix-only.
+    #[cfg(unix)]
+    fn deregister<P: AsFd>(&self, poller: &P) -> io::Result<()>;
+
+    /// Associate this pipe end with the I/O completion port `port`, so that
+    /// overlapped operations on it post their completions there with `key`.
+    /// A handle can only ever be associated with one port. This is
+    /// Windows-only.
+    #[cfg(windows)]
+    fn associate_iocp<P: AsHandle>(&self, port: &P, key: usize) -> io::Result<()>;
+}
+
+macro_rules! impl_readiness {
+    ($t:ty) => {
+        impl Readiness for $t {
+            #[cfg(unix)]
+            fn register<P: AsFd>(
+                &self,
+                poller: &P,
+                token: u64,
+                interest: Interest,
+            ) -> io::Result<()> {
+                register(&self.0, poller, Registration::Add(token, interest))
+            }
+
+            #[cfg(unix)]
+            fn reregister<P: AsFd>(
+                &self,
+                poller: &P,
+                token: u64,
+                interest: Interest,
+            ) -> io::Result<()> {
+                register(&self.0, poller, Registration::Modify(token, interest))
+            }
+
+            #[cfg(unix)]
+            fn deregister<P: AsFd>(&self, poller: &P) -> io::Result<()> {
+                register(&self.0, poller, Registration::Delete)
+            }
+
+            #[cfg(windows)]
+            fn associate_iocp<P: AsHandle>(&self, port: &P, key: usize) -> io::Result<()> {
+                sys::associate_iocp(&self.0, port.as_handle().as_raw_handle(), key)
+            }
+        }
+    };
+}
+
+impl_readiness!(PipeReader);
+impl_readiness!(PipeWriter);
+impl_readiness!(Duplex);
+
+#[cfg(unix)]
+fn register<P: AsFd>(file: &File, poller: &P, registration: Registration) -> io::Result<()> {
+    sys::register(file, poller.as_fd().as_raw_fd(), registration)
+}
@@ -0,0 +1,236 @@
+extern crate tokio;
+
+use std::io;
+#[cfg(unix)]
+use std::io::prelude::*;
+#[cfg(windows)]
+use std::mem::ManuallyDrop;
+#[cfg(windows)]
+use std::os::windows::prelude::*;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[cfg(unix)]
+use self::tokio::io::unix::AsyncFd;
+use self::tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+#[cfg(windows)]
+use self::tokio::net::windows::named_pipe::NamedPipeClient;
+
+#[cfg(windows)]
+use sys;
+use PipeReader;
+use PipeWriter;
+
+// Tokio can only drive pipes on Windows through its own NamedPipeClient, which
+// owns the handle. Keeping a second, non-owning copy of the pipe end lets
+// get_ref work the same as on Unix. Any named pipe handle works with
+// NamedPipeClient, whichever end it is, as long as it's overlapped.
+#[cfg(windows)]
+#[derive(Debug)]
+struct Registered<T> {
+    pipe: NamedPipeClient,
+    file: ManuallyDrop<T>,
+}
+
+#[cfg(windows)]
+impl<T: IntoRawHandle + FromRawHandle> Registered<T> {
+    fn new(file: T) -> io::Result<Registered<T>> {
+        let handle = file.into_raw_handle();
+        // This takes ownership even if it fails.
+        let pipe = unsafe { NamedPipeClient::from_raw_handle(handle)? };
+        Ok(Registered {
+            pipe,
+            file: ManuallyDrop::new(unsafe { T::from_raw_handle(handle) }),
+        })
+    }
+}
+
+/// A [`PipeReader`] registered with the Tokio reactor, which implements
+/// `tokio::io::AsyncRead`. This requires the `tokio` Cargo feature.
+///
+/// On Windows, the pipe has to be open for overlapped I/O, so create it with
+/// [`PipeOptions::overlapped`]. The other end can be an ordinary blocking
+/// `PipeWriter`, in this process or another.
+///
+/// [`PipeReader`]: struct.PipeReader.html
+/// [`PipeOptions::overlapped`]: struct.PipeOptions.html#method.overlapped
+#[derive(Debug)]
+pub struct AsyncPipeReader(
+    #[cfg(unix)] AsyncFd<PipeReader>,
+    #[cfg(windows)] Registered<PipeReader>,
+);
+
+impl AsyncPipeReader {
+    /// Put `reader` in non-blocking mode and register it with the current
+    /// Tokio runtime. This must be called from within a runtime that has IO
+    /// enabled. On Windows, this fails with `InvalidInput` if `reader` isn't
+    /// overlapped, and consumes it either way.
+    pub fn new(reader: PipeReader) -> io::Result<AsyncPipeReader> {
+        #[cfg(unix)]
+        {
+            reader.set_nonblocking(true)?;
+            AsyncFd::new(reader).map(AsyncPipeReader)
+        }
+        #[cfg(windows)]
+        {
+            sys::check_overlapped(&reader.0)?;
+            Registered::new(reader).map(AsyncPipeReader)
+        }
+    }
+
+    /// Get a reference to the underlying `PipeReader`. On Windows, Tokio
+    /// reads ahead into a buffer of its own, so don't read from this
+    /// directly.
+    pub fn get_ref(&self) -> &PipeReader {
+        #[cfg(unix)]
+        {
+            self.0.get_ref()
+        }
+        #[cfg(windows)]
+        {
+            &self.0.file
+        }
+    }
+
+    /// Deregister the pipe and return the underlying `PipeReader`. It's still
+    /// in non-blocking mode. This is Unix-only, because Tokio on Windows
+    /// doesn't give the handle back.
+    #[cfg(unix)]
+    pub fn into_inner(self) -> PipeReader {
+        self.0.into_inner()
+    }
+}
+
+#[cfg(windows)]
+impl AsyncRead for AsyncPipeReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0.pipe).poll_read(cx, buf)
+    }
+}
+
+#[cfg(unix)]
+impl AsyncRead for AsyncPipeReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = match self.0.poll_read_ready(cx) {
+                Poll::Ready(result) => result?,
+                Poll::Pending => return Poll::Pending,
+            };
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|inner| inner.get_ref().read(unfilled)) {
+                Ok(result) => {
+                    let n = result?;
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                // The readiness was stale, and try_io has cleared it.
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// A [`PipeWriter`] registered with the Tokio reactor, which implements
+/// `tokio::io::AsyncWrite`. This requires the `tokio` Cargo feature. On
+/// Windows, the pipe has to be overlapped, as with
+/// [`AsyncPipeReader`](struct.AsyncPipeReader.html).
+///
+/// Shutting down an `AsyncPipeWriter` doesn't close the pipe. To signal EOF to
+/// the reader, drop it.
+///
+/// [`PipeWriter`]: struct.PipeWriter.html
+#[derive(Debug)]
+pub struct AsyncPipeWriter(
+    #[cfg(unix)] AsyncFd<PipeWriter>,
+    #[cfg(windows)] Registered<PipeWriter>,
+);
+
+impl AsyncPipeWriter {
+    /// Put `writer` in non-blocking mode and register it with the current
+    /// Tokio runtime. This must be called from within a runtime that has IO
+    /// enabled. On Windows, this fails with `InvalidInput` if `writer` isn't
+    /// overlapped, and consumes it either way.
+    pub fn new(writer: PipeWriter) -> io::Result<AsyncPipeWriter> {
+        #[cfg(unix)]
+        {
+            writer.set_nonblocking(true)?;
+            AsyncFd::new(writer).map(AsyncPipeWriter)
+        }
+        #[cfg(windows)]
+        {
+            sys::check_overlapped(&writer.0)?;
+            Registered::new(writer).map(AsyncPipeWriter)
+        }
+    }
+
+    /// Get a reference to the underlying `PipeWriter`.
+    pub fn get_ref(&self) -> &PipeWriter {
+        #[cfg(unix)]
+        {
+            self.0.get_ref()
+        }
+        #[cfg(windows)]
+        {
+            &self.0.file
+        }
+    }
+
+    /// Deregister the pipe and return the underlying `PipeWriter`. It's still
+    /// in non-blocking mode. This is Unix-only, like
+    /// [`AsyncPipeReader::into_inner`](struct.AsyncPipeReader.html#method.into_inner).
+    #[cfg(unix)]
+    pub fn into_inner(self) -> PipeWriter {
+        self.0.into_inner()
+    }
+}
+
+#[cfg(windows)]
+impl AsyncWrite for AsyncPipeWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0.pipe).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0.pipe).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(unix)]
+impl AsyncWrite for AsyncPipeWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = match self.0.poll_write_ready(cx) {
+                Poll::Ready(result) => result?,
+                Poll::Pending => return Poll::Pending,
+            };
+            match guard.try_io(|inner| inner.get_ref().write(buf)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
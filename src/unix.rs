@@ -1,34 +1,267 @@
-extern crate nix;
+// This backend covers every Unix, calling libc directly. Most of it is plain
+// POSIX, and only the functions that differ between systems are cfg-gated.
+// Linux gets the most: packet mode pipes, pipe capacity, and zero-copy splice,
+// tee, and sendfile, where everything else falls back to ordinary reads and
+// writes. Redox is missing a few more things: there's no /dev/fd, there are no
+// pseudoterminals, and the libc crate has no PIPE_BUF for it.
+//
+// Fuchsia has no kernel pipes. fdio, the library that provides the POSIX file
+// descriptor layer, implements pipe() and socketpair() with zircon sockets, so
+// the ends are ordinary file descriptors that convert into Stdio like anywhere
+// else. Missing there: FIFOs, pseudoterminals, passing descriptors over a
+// socket, and passing extra descriptors to a child, since the standard
+// library's spawn only ever hands a child its stdin, stdout, and stderr.
 
+#[cfg(feature = "futures-io")]
+extern crate async_io;
+extern crate libc;
+#[cfg(feature = "mio")]
+extern crate mio;
+
+use std::cmp;
+#[cfg(not(target_os = "fuchsia"))]
+use std::ffi::CString;
+#[cfg(not(target_os = "fuchsia"))]
+use std::fs;
 use std::fs::File;
 use std::io;
+use std::io::prelude::*;
+use std::mem;
 use std::mem::ManuallyDrop;
+use std::net::Shutdown;
+use std::os::raw::c_int;
 use std::os::unix::prelude::*;
+#[cfg(not(target_os = "fuchsia"))]
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+#[cfg(not(target_os = "fuchsia"))]
+use std::path::PathBuf;
+use std::process::Command;
+#[cfg(not(target_os = "fuchsia"))]
+use std::ptr;
+#[cfg(not(target_os = "fuchsia"))]
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
 
+use capture::StdStream;
+use readiness::Registration;
+use Duplex;
+use PipeOptions;
 use PipeReader;
 use PipeWriter;
+use PollEntry;
+use PtyMaster;
+use PtySlave;
 
-pub(crate) fn pipe() -> io::Result<(PipeReader, PipeWriter)> {
-    // O_CLOEXEC prevents children from inheriting these pipes. Nix's pipe2() will make a best
-    // effort to make that atomic on platforms that support it, to avoid the case where another
-    // thread forks right after the pipes are created but before O_CLOEXEC is set.
-    let (read_fd, write_fd) =
-        nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC).map_err(nix_err_to_io_err)?;
+// Turn a -1 return into the current errno.
+fn cvt<T: IsMinusOne>(ret: T) -> io::Result<T> {
+    if ret.is_minus_one() {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
 
-    unsafe {
-        Ok((
+// Like cvt, but retry on EINTR. Even calls that don't block can fail that way
+// if a signal arrives at the wrong moment, and there's nothing useful for the
+// caller to do about it. Reads and writes are the exception. Those still
+// return Interrupted, like the standard library's, so that a signal can break
+// out of a blocking read.
+fn cvt_r<T: IsMinusOne, F: FnMut() -> T>(mut f: F) -> io::Result<T> {
+    loop {
+        match cvt(f()) {
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+trait IsMinusOne {
+    fn is_minus_one(&self) -> bool;
+}
+
+impl IsMinusOne for c_int {
+    fn is_minus_one(&self) -> bool {
+        *self == -1
+    }
+}
+
+impl IsMinusOne for isize {
+    fn is_minus_one(&self) -> bool {
+        *self == -1
+    }
+}
+
+pub(crate) fn pipe(options: &PipeOptions) -> io::Result<(PipeReader, PipeWriter)> {
+    let mut flags = 0;
+    if !options.inheritable {
+        // This prevents children from inheriting these pipes.
+        flags |= libc::O_CLOEXEC;
+    }
+    if options.nonblocking {
+        flags |= libc::O_NONBLOCK;
+    }
+    if options.message_mode {
+        flags |= message_mode_flag()?;
+    }
+    let (read_fd, write_fd) = pipe2(flags)?;
+    let (reader, writer) = unsafe {
+        (
             PipeReader::from_raw_fd(read_fd),
             PipeWriter::from_raw_fd(write_fd),
-        ))
+        )
+    };
+
+    // The reader and writer share a buffer, so setting it on either is enough.
+    // Where pipes can't be resized, the capacity is only a hint anyway.
+    if let Some(capacity) = options.capacity {
+        if cfg!(any(target_os = "android", target_os = "linux")) {
+            set_capacity(&writer.0, capacity)?;
+        }
     }
+    Ok((reader, writer))
 }
 
-fn nix_err_to_io_err(err: nix::Error) -> io::Error {
-    if let nix::Error::Sys(err_no) = err {
-        io::Error::from(err_no)
-    } else {
-        panic!("unexpected nix error type: {:?}", err)
+// pipe2() sets O_CLOEXEC atomically, which matters in multithreaded programs.
+// If another thread forks between a plain pipe() and the fcntl() that follows
+// it, the child inherits both ends, and the reader never sees EOF while that
+// child is alive. Linux only added pipe2() in 2.6.27, so older kernels fail it
+// with ENOSYS, and we remember that and fall back. There's no fork() on
+// Fuchsia, so there O_CLOEXEC only matters for code that calls fdio_spawn with
+// FDIO_SPAWN_CLONE_ALL.
+#[cfg(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "fuchsia",
+    target_os = "linux",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "redox"
+))]
+fn pipe2(flags: c_int) -> io::Result<(RawFd, RawFd)> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static HAS_PIPE2: AtomicBool = AtomicBool::new(true);
+
+    if HAS_PIPE2.load(Ordering::Relaxed) {
+        let mut fds = [0; 2];
+        match cvt_r(|| unsafe { libc::pipe2(fds.as_mut_ptr(), flags) }) {
+            Ok(_) => return Ok((fds[0], fds[1])),
+            Err(ref e) if e.raw_os_error() == Some(libc::ENOSYS) => {}
+            Err(e) => return Err(e),
+        }
+        HAS_PIPE2.store(false, Ordering::Relaxed);
+    }
+    pipe_then_fcntl(flags)
+}
+
+// macOS and the other Unixes without pipe2() leave a window between creating
+// the pipe and making it close-on-exec. There's no way around that short of a
+// lock shared with every fork() in the process, which a library can't impose.
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "fuchsia",
+    target_os = "linux",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "redox"
+)))]
+fn pipe2(flags: c_int) -> io::Result<(RawFd, RawFd)> {
+    pipe_then_fcntl(flags)
+}
+
+fn pipe_then_fcntl(flags: c_int) -> io::Result<(RawFd, RawFd)> {
+    let mut fds = [0; 2];
+    cvt_r(|| unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+    let files = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    for file in &[&files.0, &files.1] {
+        if flags & libc::O_CLOEXEC != 0 {
+            set_cloexec(file)?;
+        }
+        let status_flags = flags & !libc::O_CLOEXEC;
+        if status_flags != 0 {
+            let fd = file.as_raw_fd();
+            let bits = cvt_r(|| unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+            cvt_r(|| unsafe { libc::fcntl(fd, libc::F_SETFL, bits | status_flags) })?;
+        }
     }
+    Ok((files.0.into_raw_fd(), files.1.into_raw_fd()))
+}
+
+// Packet mode pipes were added in Linux 3.4. Older kernels fail pipe2 with
+// EINVAL.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn message_mode_flag() -> io::Result<c_int> {
+    Ok(libc::O_DIRECT)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn message_mode_flag() -> io::Result<c_int> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "message mode pipes are only available on Linux and Windows",
+    ))
+}
+
+pub(crate) fn duplex() -> io::Result<(File, File)> {
+    // As with pipe2() above, ask for SOCK_CLOEXEC up front where the platform
+    // supports it, to avoid racing against fork().
+    #[cfg(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "linux",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "redox"
+    ))]
+    let flags = libc::SOCK_CLOEXEC;
+    #[cfg(not(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "linux",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "redox"
+    )))]
+    let flags = 0;
+
+    let mut fds = [0; 2];
+    cvt_r(|| unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_STREAM | flags,
+            0,
+            fds.as_mut_ptr(),
+        )
+    })?;
+    let (a, b) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    if flags == 0 {
+        set_cloexec(&a)?;
+        set_cloexec(&b)?;
+    }
+    Ok((a, b))
+}
+
+pub(crate) fn shutdown(file: &File, how: Shutdown) -> io::Result<()> {
+    let how = match how {
+        Shutdown::Read => libc::SHUT_RD,
+        Shutdown::Write => libc::SHUT_WR,
+        Shutdown::Both => libc::SHUT_RDWR,
+    };
+    cvt(unsafe { libc::shutdown(file.as_raw_fd(), how) })?;
+    Ok(())
+}
+
+fn set_cloexec(file: &File) -> io::Result<()> {
+    cvt_r(|| unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) })?;
+    Ok(())
 }
 
 pub(crate) fn dup<T: AsRawFd>(wrapper: T) -> io::Result<File> {
@@ -37,38 +270,1161 @@ pub(crate) fn dup<T: AsRawFd>(wrapper: T) -> io::Result<File> {
     temp_file.try_clone()
 }
 
-impl IntoRawFd for PipeReader {
-    fn into_raw_fd(self) -> RawFd {
-        self.0.into_raw_fd()
+// The per-process and system-wide limits on open files.
+pub(crate) fn is_out_of_descriptors(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EMFILE) | Some(libc::ENFILE))
+}
+
+// dup() fails this way when the standard stream it's given is closed.
+pub(crate) fn is_closed_std_stream(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EBADF)
+}
+
+pub(crate) fn read(file: &File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut file_ref = file;
+    file_ref.read(buf)
+}
+
+pub(crate) fn write(file: &File, buf: &[u8]) -> io::Result<usize> {
+    let mut file_ref = file;
+    file_ref.write(buf)
+}
+
+#[cfg(not(target_os = "redox"))]
+const PIPE_BUF: usize = libc::PIPE_BUF;
+
+// The libc crate doesn't have PIPE_BUF for Redox. Use the smallest value POSIX
+// allows.
+#[cfg(target_os = "redox")]
+const PIPE_BUF: usize = 512;
+
+pub(crate) fn write_atomic(file: &File, buf: &[u8]) -> io::Result<()> {
+    if buf.len() > PIPE_BUF {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "writes larger than PIPE_BUF aren't atomic",
+        ));
     }
+    ::write_once(file, buf)
+}
+
+pub(crate) fn write_timeout(file: &File, buf: &[u8], timeout: Duration) -> io::Result<usize> {
+    ::write_polled(file, buf, timeout, PIPE_BUF)
 }
 
-impl AsRawFd for PipeReader {
-    fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
+// Write the wakeup byte from a signal handler. That means nothing but raw
+// syscalls, and errors are ignored, since a full pipe already has a wakeup
+// pending and a closed one has nobody to wake. A handler also has to leave
+// errno the way it found it, where we know how to find errno.
+pub(crate) fn notify(file: &File) {
+    let errno = errno_location();
+    let saved = errno.map(|errno| unsafe { *errno });
+    unsafe {
+        libc::write(file.as_raw_fd(), [1u8].as_ptr() as *const _, 1);
     }
+    if let (Some(errno), Some(saved)) = (errno, saved) {
+        unsafe { *errno = saved };
+    }
+}
+
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "emscripten",
+    target_os = "fuchsia",
+    target_os = "hurd",
+    target_os = "l4re",
+    target_os = "linux",
+    target_os = "redox"
+))]
+fn errno_location() -> Option<*mut c_int> {
+    Some(unsafe { libc::__errno_location() })
+}
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "cygwin",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn errno_location() -> Option<*mut c_int> {
+    Some(unsafe { libc::__errno() })
+}
+
+#[cfg(any(target_os = "freebsd", target_vendor = "apple"))]
+fn errno_location() -> Option<*mut c_int> {
+    Some(unsafe { libc::__error() })
+}
+
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+fn errno_location() -> Option<*mut c_int> {
+    Some(unsafe { libc::___errno() })
+}
+
+#[cfg(target_os = "haiku")]
+fn errno_location() -> Option<*mut c_int> {
+    Some(unsafe { libc::_errnop() })
 }
 
-impl FromRawFd for PipeReader {
-    unsafe fn from_raw_fd(fd: RawFd) -> PipeReader {
-        PipeReader(File::from_raw_fd(fd))
+#[cfg(target_os = "aix")]
+fn errno_location() -> Option<*mut c_int> {
+    Some(unsafe { libc::_Errno() })
+}
+
+#[cfg(target_os = "nto")]
+fn errno_location() -> Option<*mut c_int> {
+    Some(unsafe { libc::__get_errno_ptr() })
+}
+
+// Anywhere else the write might clobber errno, which is better than not
+// building at all.
+#[cfg(not(any(
+    target_os = "aix",
+    target_os = "android",
+    target_os = "cygwin",
+    target_os = "dragonfly",
+    target_os = "emscripten",
+    target_os = "freebsd",
+    target_os = "fuchsia",
+    target_os = "haiku",
+    target_os = "hurd",
+    target_os = "illumos",
+    target_os = "l4re",
+    target_os = "linux",
+    target_os = "netbsd",
+    target_os = "nto",
+    target_os = "openbsd",
+    target_os = "redox",
+    target_os = "solaris",
+    target_vendor = "apple"
+)))]
+fn errno_location() -> Option<*mut c_int> {
+    None
+}
+
+// File uses readv and writev for these.
+pub(crate) fn read_vectored(file: &File, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+    let mut file_ref = file;
+    file_ref.read_vectored(bufs)
+}
+
+pub(crate) fn write_vectored(file: &File, bufs: &[io::IoSlice]) -> io::Result<usize> {
+    let mut file_ref = file;
+    file_ref.write_vectored(bufs)
+}
+
+pub(crate) fn set_nonblocking(file: &File, nonblocking: bool) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    let flags = cvt_r(|| unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+    let flags = if nonblocking {
+        flags | libc::O_NONBLOCK
+    } else {
+        flags & !libc::O_NONBLOCK
+    };
+    cvt_r(|| unsafe { libc::fcntl(fd, libc::F_SETFL, flags) })?;
+    Ok(())
+}
+
+// Don't retry on EINTR. Linux and most other systems have already closed the
+// fd by then, and retrying could close one that another thread just opened.
+pub(crate) fn close(file: File) -> io::Result<()> {
+    let fd = file.into_raw_fd();
+    cvt(unsafe { libc::close(fd) })?;
+    Ok(())
+}
+
+pub(crate) fn set_inheritable(file: &File, inheritable: bool) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    let flags = cvt_r(|| unsafe { libc::fcntl(fd, libc::F_GETFD) })?;
+    let flags = if inheritable {
+        flags & !libc::FD_CLOEXEC
+    } else {
+        flags | libc::FD_CLOEXEC
+    };
+    cvt_r(|| unsafe { libc::fcntl(fd, libc::F_SETFD, flags) })?;
+    Ok(())
+}
+
+// The highest fd number any pipe has been passed to a child as, or stderr's.
+// Pre-exec hooks run in the order they're added, so one hook's dup2 would
+// replace a pipe that a later hook is about to pass, if its fd number happened
+// to be the first hook's target. Command has nowhere to gather all the pipes
+// into one hook, so instead each pipe is moved above every target so far
+// before its hook is added. Targets added afterwards can't hurt it, because
+// by the time their hooks run, it's already where it's going.
+#[cfg(not(target_os = "fuchsia"))]
+static HIGHEST_TARGET: AtomicI32 = AtomicI32::new(2);
+
+// Give the child `src` as fd `target`, or at its own fd number if `target` is
+// None, and return the fd number the child will see.
+#[cfg(not(target_os = "fuchsia"))]
+pub(crate) fn pass_fd(command: &mut Command, src: OwnedFd, target: Option<RawFd>) -> RawFd {
+    let highest = HIGHEST_TARGET.load(Ordering::Relaxed);
+    let src = if src.as_raw_fd() > highest {
+        src
+    } else {
+        // If that fails, most likely because `highest` is near the fd limit,
+        // the pipe stays where it is, and only collides if an earlier target
+        // really is its number.
+        match cvt(unsafe { libc::fcntl(src.as_raw_fd(), libc::F_DUPFD_CLOEXEC, highest + 1) }) {
+            Ok(fd) => unsafe { OwnedFd::from_raw_fd(fd) },
+            Err(_) => src,
+        }
+    };
+    // A pipe passed at its own fd number can't be a later pipe's number,
+    // since the Command keeps it open.
+    let target = match target {
+        Some(target) => {
+            HIGHEST_TARGET.fetch_max(target, Ordering::Relaxed);
+            target
+        }
+        None => src.as_raw_fd(),
+    };
+    inherit_in_child(command, src, target);
+    target
+}
+
+// Make `src` available to the child as `target`, after the fork and before the
+// exec. The hook keeps `src` alive, so an owned fd stays open for as long as
+// the Command does.
+#[cfg(not(target_os = "fuchsia"))]
+fn inherit_in_child<S>(command: &mut Command, src: S, target: RawFd)
+where
+    S: AsRawFd + Send + Sync + 'static,
+{
+    let hook = move || {
+        let src = src.as_raw_fd();
+        // Only async-signal-safe calls are allowed here, so no allocating.
+        unsafe {
+            if src == target {
+                let flags = cvt(libc::fcntl(src, libc::F_GETFD))?;
+                cvt(libc::fcntl(src, libc::F_SETFD, flags & !libc::FD_CLOEXEC))?;
+            } else {
+                // The new descriptor doesn't have FD_CLOEXEC.
+                cvt(libc::dup2(src, target))?;
+            }
+        }
+        Ok(())
+    };
+    unsafe {
+        command.pre_exec(hook);
+    }
+}
+
+// The child opens the pipe again through /dev/fd, which on Linux is a symlink
+// into /proc/self/fd.
+#[cfg(not(any(target_os = "fuchsia", target_os = "redox")))]
+pub(crate) fn child_path(
+    file: &File,
+    command: &mut Command,
+    _reading: bool,
+) -> io::Result<PathBuf> {
+    let fd = file.as_raw_fd();
+    inherit_in_child(command, fd, fd);
+    Ok(PathBuf::from(format!("/dev/fd/{}", fd)))
+}
+
+#[cfg(target_os = "redox")]
+pub(crate) fn child_path(
+    _file: &File,
+    _command: &mut Command,
+    _reading: bool,
+) -> io::Result<PathBuf> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Redox has no /dev/fd",
+    ))
+}
+
+pub(crate) fn raw_value(file: &File) -> u64 {
+    file.as_raw_fd() as u64
+}
+
+pub(crate) unsafe fn from_inherited_raw(raw: u64) -> io::Result<File> {
+    if raw > c_int::MAX as u64 {
+        return Err(not_a_pipe());
+    }
+    let fd = raw as RawFd;
+    // This also fails with EBADF if the fd isn't open.
+    let mut stat: libc::stat = mem::zeroed();
+    cvt_r(|| libc::fstat(fd, &mut stat))?;
+    if !is_pipe_type(stat.st_mode & libc::S_IFMT) {
+        return Err(not_a_pipe());
     }
+    let file = File::from_raw_fd(fd);
+    set_inheritable(&file, false)?;
+    Ok(file)
 }
 
-impl IntoRawFd for PipeWriter {
-    fn into_raw_fd(self) -> RawFd {
-        self.0.into_raw_fd()
+#[cfg(not(target_os = "fuchsia"))]
+fn is_pipe_type(file_type: libc::mode_t) -> bool {
+    file_type == libc::S_IFIFO
+}
+
+// fdio's pipes are zircon sockets, and may report either type.
+#[cfg(target_os = "fuchsia")]
+fn is_pipe_type(file_type: libc::mode_t) -> bool {
+    file_type == libc::S_IFIFO || file_type == libc::S_IFSOCK
+}
+
+fn not_a_pipe() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "inherited handle is not a pipe",
+    )
+}
+
+#[derive(Debug)]
+pub(crate) struct StdCapture {
+    saved: File,
+    fd: RawFd,
+}
+
+impl StdCapture {
+    pub(crate) fn start(stream: StdStream, writer: File) -> io::Result<StdCapture> {
+        let (saved, fd) = match stream {
+            StdStream::Stdout => (dup(io::stdout())?, 1),
+            StdStream::Stderr => (dup(io::stderr())?, 2),
+        };
+        // The new fd 1 or 2 doesn't have FD_CLOEXEC, so child processes
+        // inherit the capture like they would the original. Dropping `writer`
+        // afterwards leaves the dup2 copy as the only write end.
+        cvt_r(|| unsafe { libc::dup2(writer.as_raw_fd(), fd) })?;
+        Ok(StdCapture { saved, fd })
+    }
+}
+
+impl Drop for StdCapture {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dup2(self.saved.as_raw_fd(), self.fd);
+        }
+    }
+}
+
+#[cfg(not(target_os = "fuchsia"))]
+#[derive(Debug)]
+pub(crate) struct NamedPipe {
+    path: PathBuf,
+}
+
+#[cfg(not(target_os = "fuchsia"))]
+impl NamedPipe {
+    pub(crate) fn create(path: &Path) -> io::Result<NamedPipe> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        cvt_r(|| unsafe { libc::mkfifo(c_path.as_ptr(), libc::S_IRUSR | libc::S_IWUSR) })?;
+        Ok(NamedPipe {
+            path: path.to_owned(),
+        })
+    }
+
+    pub(crate) fn accept_reader(self) -> io::Result<File> {
+        // Opening a FIFO blocks until the other end is opened too. The FIFO
+        // gets unlinked when self drops, after which the open pipe keeps
+        // working but no one else can connect to it.
+        open_named_pipe_reader(&self.path)
+    }
+
+    pub(crate) fn accept_writer(self) -> io::Result<File> {
+        open_named_pipe_writer(&self.path)
     }
 }
 
-impl AsRawFd for PipeWriter {
-    fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
+#[cfg(not(target_os = "fuchsia"))]
+impl Drop for NamedPipe {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
     }
 }
 
-impl FromRawFd for PipeWriter {
-    unsafe fn from_raw_fd(fd: RawFd) -> PipeWriter {
-        PipeWriter(File::from_raw_fd(fd))
+#[cfg(not(target_os = "fuchsia"))]
+pub(crate) fn open_named_pipe_reader(path: &Path) -> io::Result<File> {
+    // The standard library opens files with O_CLOEXEC.
+    fs::OpenOptions::new().read(true).open(path)
+}
+
+#[cfg(not(target_os = "fuchsia"))]
+pub(crate) fn open_named_pipe_writer(path: &Path) -> io::Result<File> {
+    fs::OpenOptions::new().write(true).open(path)
+}
+
+#[cfg(target_os = "fuchsia")]
+fn unsupported_fifo() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "named pipes aren't available on Fuchsia",
+    )
+}
+
+#[cfg(target_os = "fuchsia")]
+#[derive(Debug)]
+pub(crate) struct NamedPipe(());
+
+#[cfg(target_os = "fuchsia")]
+impl NamedPipe {
+    pub(crate) fn create(_path: &Path) -> io::Result<NamedPipe> {
+        Err(unsupported_fifo())
+    }
+
+    pub(crate) fn accept_reader(self) -> io::Result<File> {
+        Err(unsupported_fifo())
+    }
+
+    pub(crate) fn accept_writer(self) -> io::Result<File> {
+        Err(unsupported_fifo())
     }
 }
+
+#[cfg(target_os = "fuchsia")]
+pub(crate) fn open_named_pipe_reader(_path: &Path) -> io::Result<File> {
+    Err(unsupported_fifo())
+}
+
+#[cfg(target_os = "fuchsia")]
+pub(crate) fn open_named_pipe_writer(_path: &Path) -> io::Result<File> {
+    Err(unsupported_fifo())
+}
+
+pub(crate) fn poll(entries: &mut [PollEntry], timeout: Option<Duration>) -> io::Result<usize> {
+    let mut fds: Vec<libc::pollfd> = entries
+        .iter()
+        .map(|entry| libc::pollfd {
+            fd: entry.file.as_raw_fd(),
+            events: if entry.writable {
+                libc::POLLOUT
+            } else {
+                libc::POLLIN
+            },
+            revents: 0,
+        })
+        .collect();
+    poll_fds(&mut fds, timeout)?;
+    let mut count = 0;
+    for (entry, fd) in entries.iter_mut().zip(&fds) {
+        // POLLHUP and POLLERR mean that a read would return EOF or a write
+        // would fail, so they count as ready too.
+        entry.ready = fd.revents != 0;
+        if entry.ready {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+// poll() always reports POLLERR (pipes) or POLLHUP (sockets) once the other
+// end is gone, whether or not there's room to write.
+pub(crate) fn is_peer_closed(file: &File) -> io::Result<bool> {
+    let mut fds = [libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLOUT,
+        revents: 0,
+    }];
+    poll_fds(&mut fds, Some(Duration::from_secs(0)))?;
+    Ok(fds[0].revents & (libc::POLLERR | libc::POLLHUP) != 0)
+}
+
+// Call poll(), retrying on EINTR with whatever's left of the timeout. No
+// timeout means wait forever.
+fn poll_fds(fds: &mut [libc::pollfd], timeout: Option<Duration>) -> io::Result<usize> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let millis = match deadline {
+            Some(deadline) => timeout_millis(deadline.saturating_duration_since(Instant::now())),
+            None => -1,
+        };
+        match cvt(unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, millis) }) {
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result.map(|n| n as usize),
+        }
+    }
+}
+
+// poll() takes milliseconds. Round up, so that a short timeout doesn't turn
+// into a busy loop.
+fn timeout_millis(timeout: Duration) -> c_int {
+    let millis = timeout.as_nanos().div_ceil(1_000_000);
+    cmp::min(millis, c_int::MAX as u128) as c_int
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(crate) fn capacity(file: &File) -> io::Result<usize> {
+    let size = cvt_r(|| unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETPIPE_SZ) })?;
+    Ok(size as usize)
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(crate) fn set_capacity(file: &File, capacity: usize) -> io::Result<usize> {
+    if capacity > c_int::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "pipe capacity too large",
+        ));
+    }
+    let size =
+        cvt_r(|| unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETPIPE_SZ, capacity as c_int) })?;
+    Ok(size as usize)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub(crate) fn capacity(_file: &File) -> io::Result<usize> {
+    Err(unsupported_capacity())
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub(crate) fn set_capacity(_file: &File, _capacity: usize) -> io::Result<usize> {
+    Err(unsupported_capacity())
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn unsupported_capacity() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pipe capacity is only available on Linux",
+    )
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(crate) fn splice(from: &File, to: &File, len: usize) -> io::Result<usize> {
+    let ret = cvt_r(|| unsafe {
+        libc::splice(
+            from.as_raw_fd(),
+            ptr::null_mut(),
+            to.as_raw_fd(),
+            ptr::null_mut(),
+            len,
+            0,
+        )
+    });
+    match ret {
+        // EINVAL means the file doesn't support splice. For example, it's
+        // opened with O_APPEND.
+        Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) => ::copy_chunk(from, to, len),
+        result => result.map(|n| n as usize),
+    }
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub(crate) fn splice(from: &File, to: &File, len: usize) -> io::Result<usize> {
+    ::copy_chunk(from, to, len)
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(crate) fn send_file(to: &File, from: &File, offset: u64, len: u64) -> io::Result<u64> {
+    use self::libc::off_t;
+
+    if offset > off_t::MAX as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "offset too large",
+        ));
+    }
+    let mut file_offset = offset as off_t;
+    let mut sent = 0;
+    while sent < len {
+        // sendfile moves at most about 2 GiB per call, so go 1 GiB at a time.
+        let count = cmp::min(len - sent, 1 << 30) as usize;
+        let ret = cvt_r(|| unsafe {
+            libc::sendfile(to.as_raw_fd(), from.as_raw_fd(), &mut file_offset, count)
+        });
+        match ret {
+            Ok(0) => break,
+            Ok(n) => sent += n as u64,
+            // EINVAL means the file can't be mmapped, like a FIFO or a tty.
+            Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) && sent == 0 => {
+                return send_file_fallback(to, from, offset, len);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(sent)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub(crate) fn send_file(to: &File, from: &File, offset: u64, len: u64) -> io::Result<u64> {
+    // sendfile exists on the BSDs and macOS, but only for sending to sockets.
+    send_file_fallback(to, from, offset, len)
+}
+
+fn send_file_fallback(mut to: &File, from: &File, offset: u64, len: u64) -> io::Result<u64> {
+    use std::os::unix::fs::FileExt;
+
+    let mut buf = vec![0; cmp::min(len, 1 << 16) as usize];
+    let mut sent = 0;
+    while sent < len {
+        let count = cmp::min(len - sent, buf.len() as u64) as usize;
+        let n = match from.read_at(&mut buf[..count], offset + sent) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        to.write_all(&buf[..n])?;
+        sent += n as u64;
+    }
+    Ok(sent)
+}
+
+pub(crate) fn copy<R: AsFd, W: AsFd>(from: &R, to: &W) -> io::Result<u64> {
+    // The standard library already has these fast paths, but only for its
+    // own types. Borrow the descriptors as Files without taking ownership.
+    let from = ManuallyDrop::new(unsafe { File::from_raw_fd(from.as_fd().as_raw_fd()) });
+    let to = ManuallyDrop::new(unsafe { File::from_raw_fd(to.as_fd().as_raw_fd()) });
+    io::copy(&mut &*from, &mut &*to)
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(crate) fn tee(from: &File, to: &File, len: usize) -> io::Result<usize> {
+    let n = cvt_r(|| unsafe { libc::tee(from.as_raw_fd(), to.as_raw_fd(), len, 0) })?;
+    Ok(n as usize)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub(crate) fn tee(_from: &File, _to: &File, _len: usize) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "tee is only available on Linux",
+    ))
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(crate) fn register(file: &File, poller: RawFd, registration: Registration) -> io::Result<()> {
+    let (op, token, interest) = match registration {
+        Registration::Add(token, interest) => (libc::EPOLL_CTL_ADD, token, Some(interest)),
+        Registration::Modify(token, interest) => (libc::EPOLL_CTL_MOD, token, Some(interest)),
+        Registration::Delete => (libc::EPOLL_CTL_DEL, 0, None),
+    };
+    let mut events = 0;
+    if let Some(interest) = interest {
+        if interest.is_readable() {
+            events |= libc::EPOLLIN | libc::EPOLLRDHUP;
+        }
+        if interest.is_writable() {
+            events |= libc::EPOLLOUT;
+        }
+    }
+    let mut event = libc::epoll_event {
+        events: events as u32,
+        u64: token,
+    };
+    cvt(unsafe { libc::epoll_ctl(poller, op, file.as_raw_fd(), &mut event) })?;
+    Ok(())
+}
+
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+pub(crate) fn register(file: &File, poller: RawFd, registration: Registration) -> io::Result<()> {
+    // Report double adds and missing fds the way epoll does.
+    let registered = is_registered(file, poller)?;
+    let (token, interest) = match registration {
+        Registration::Add(..) if registered => {
+            return Err(io::Error::from_raw_os_error(libc::EEXIST));
+        }
+        Registration::Modify(..) | Registration::Delete if !registered => {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
+        Registration::Add(token, interest) | Registration::Modify(token, interest) => {
+            (token, Some(interest))
+        }
+        Registration::Delete => (0, None),
+    };
+    let wanted = [
+        (libc::EVFILT_READ, interest.is_some_and(|i| i.is_readable())),
+        (
+            libc::EVFILT_WRITE,
+            interest.is_some_and(|i| i.is_writable()),
+        ),
+    ];
+    // kqueue has one registration per filter. Add the ones that are wanted
+    // and delete the others, and EV_RECEIPT reports how each change went.
+    let mut changes: [libc::kevent; 2] = unsafe { mem::zeroed() };
+    for (change, &(filter, wanted)) in changes.iter_mut().zip(&wanted) {
+        change.ident = file.as_raw_fd() as _;
+        change.filter = filter;
+        change.flags = libc::EV_RECEIPT
+            | if wanted {
+                libc::EV_ADD
+            } else {
+                libc::EV_DELETE
+            };
+        change.udata = token as usize as _;
+    }
+    let mut receipts: [libc::kevent; 2] = unsafe { mem::zeroed() };
+    let n = cvt(unsafe {
+        libc::kevent(
+            poller,
+            changes.as_ptr(),
+            changes.len() as _,
+            receipts.as_mut_ptr(),
+            receipts.len() as _,
+            ptr::null(),
+        )
+    })?;
+    for receipt in &receipts[..n as usize] {
+        let errno = receipt.data as c_int;
+        // Deleting a filter that was never added is fine.
+        if receipt.flags & libc::EV_ERROR != 0 && errno != 0 && errno != libc::ENOENT {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+    }
+    Ok(())
+}
+
+// kqueue can't be asked what's registered, but enabling a filter fails with
+// ENOENT if it isn't there, and does nothing to one we added, since those are
+// always enabled.
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn is_registered(file: &File, poller: RawFd) -> io::Result<bool> {
+    let mut changes: [libc::kevent; 2] = unsafe { mem::zeroed() };
+    for (change, &filter) in changes
+        .iter_mut()
+        .zip(&[libc::EVFILT_READ, libc::EVFILT_WRITE])
+    {
+        change.ident = file.as_raw_fd() as _;
+        change.filter = filter;
+        change.flags = libc::EV_RECEIPT | libc::EV_ENABLE;
+    }
+    let mut receipts: [libc::kevent; 2] = unsafe { mem::zeroed() };
+    let n = cvt(unsafe {
+        libc::kevent(
+            poller,
+            changes.as_ptr(),
+            changes.len() as _,
+            receipts.as_mut_ptr(),
+            receipts.len() as _,
+            ptr::null(),
+        )
+    })?;
+    let mut registered = false;
+    for receipt in &receipts[..n as usize] {
+        match receipt.data as c_int {
+            0 => registered = true,
+            libc::ENOENT => {}
+            errno => return Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+    Ok(registered)
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+pub(crate) fn register(
+    _file: &File,
+    _poller: RawFd,
+    _registration: Registration,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "readiness registration is only available with epoll or kqueue",
+    ))
+}
+
+pub(crate) fn bytes_available(file: &File) -> io::Result<usize> {
+    let mut available: c_int = 0;
+    cvt(unsafe { libc::ioctl(file.as_raw_fd(), libc::FIONREAD, &mut available) })?;
+    Ok(available as usize)
+}
+
+// Pipes don't support MSG_PEEK, but on Linux we can tee the buffered bytes
+// into a scratch pipe and read them from there.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(crate) fn peek(file: &File, buf: &mut [u8]) -> io::Result<usize> {
+    let (mut scratch_reader, scratch_writer) = pipe(&PipeOptions::new())?;
+    let ret = cvt_r(|| unsafe {
+        libc::tee(
+            file.as_raw_fd(),
+            scratch_writer.as_raw_fd(),
+            buf.len(),
+            libc::SPLICE_F_NONBLOCK,
+        )
+    });
+    let n = match ret {
+        Ok(n) => n as usize,
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    drop(scratch_writer);
+    scratch_reader.read_exact(&mut buf[..n])?;
+    Ok(n)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub(crate) fn peek(_file: &File, _buf: &mut [u8]) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "peeking into a pipe is only possible on Linux",
+    ))
+}
+
+macro_rules! impl_fd_traits {
+    ($t:ident) => {
+        impl IntoRawFd for $t {
+            fn into_raw_fd(self) -> RawFd {
+                self.0.into_raw_fd()
+            }
+        }
+
+        impl AsRawFd for $t {
+            fn as_raw_fd(&self) -> RawFd {
+                self.0.as_raw_fd()
+            }
+        }
+
+        impl AsFd for $t {
+            fn as_fd(&self) -> BorrowedFd<'_> {
+                self.0.as_fd()
+            }
+        }
+
+        impl From<$t> for OwnedFd {
+            fn from(p: $t) -> OwnedFd {
+                p.0.into()
+            }
+        }
+    };
+}
+
+// Pipe ends and duplexes can also be made from any descriptor. Pty ends can't,
+// because the master and slave have to come from the same openpty().
+macro_rules! impl_from_fd {
+    ($t:ident) => {
+        impl From<OwnedFd> for $t {
+            fn from(fd: OwnedFd) -> $t {
+                $t(File::from(fd))
+            }
+        }
+
+        impl FromRawFd for $t {
+            unsafe fn from_raw_fd(fd: RawFd) -> $t {
+                $t(File::from_raw_fd(fd))
+            }
+        }
+    };
+}
+
+impl_fd_traits!(PipeReader);
+impl_fd_traits!(PipeWriter);
+impl_fd_traits!(Duplex);
+impl_fd_traits!(PtyMaster);
+impl_fd_traits!(PtySlave);
+impl_from_fd!(PipeReader);
+impl_from_fd!(PipeWriter);
+impl_from_fd!(Duplex);
+
+// Send `fd` with one byte of ordinary data, using sendmsg() with SCM_RIGHTS.
+#[cfg(not(target_os = "fuchsia"))]
+pub(crate) fn send_fd(socket: &File, fd: BorrowedFd) -> io::Result<()> {
+    let mut data = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut _,
+        iov_len: data.len(),
+    };
+    let space = unsafe { libc::CMSG_SPACE(mem::size_of::<c_int>() as _) } as usize;
+    // u64 words keep the buffer aligned for cmsghdr.
+    let mut control = vec![0u64; space.div_ceil(8)];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut _;
+    msg.msg_controllen = space as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<c_int>() as _) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut c_int, fd.as_raw_fd());
+    }
+    cvt_r(|| unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) })?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "fuchsia"))]
+pub(crate) fn recv_fd(socket: &File) -> io::Result<OwnedFd> {
+    let mut data = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut _,
+        iov_len: data.len(),
+    };
+    // Leave room for a few descriptors, so that a confused peer sending
+    // more than one doesn't truncate the message. The extras get closed.
+    let space = unsafe { libc::CMSG_SPACE((4 * mem::size_of::<c_int>()) as _) } as usize;
+    let mut control = vec![0u64; space.div_ceil(8)];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut _;
+    msg.msg_controllen = space as _;
+    let n = cvt_r(|| unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, recv_fd_flags()) })?;
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const c_int;
+                let header_len = libc::CMSG_LEN(0) as usize;
+                let count = ((*cmsg).cmsg_len as usize - header_len) / mem::size_of::<c_int>();
+                for i in 0..count {
+                    let fd = ptr::read_unaligned(data.add(i));
+                    fds.push(OwnedFd::from_raw_fd(fd));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    // The kernel drops whatever didn't fit, and the descriptors it dropped
+    // are gone for good, so this can't just take the first one and carry on.
+    // Dropping the ones that did arrive closes them.
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "too many file descriptors arrived, and some were lost",
+        ));
+    }
+    if n == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed before a file descriptor arrived",
+        ));
+    }
+    if fds.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message didn't carry a file descriptor",
+        ));
+    }
+    // Dropping the rest closes them.
+    let fd = fds.swap_remove(0);
+    if recv_fd_flags() == 0 {
+        set_cloexec(&ManuallyDrop::new(unsafe {
+            File::from_raw_fd(fd.as_raw_fd())
+        }))?;
+    }
+    Ok(fd)
+}
+
+// zircon sockets carry bytes only. Handles go over channels instead, which
+// this crate doesn't wrap.
+#[cfg(target_os = "fuchsia")]
+pub(crate) fn send_fd(_socket: &File, _fd: BorrowedFd) -> io::Result<()> {
+    Err(unsupported_fd_passing())
+}
+
+#[cfg(target_os = "fuchsia")]
+pub(crate) fn recv_fd(_socket: &File) -> io::Result<OwnedFd> {
+    Err(unsupported_fd_passing())
+}
+
+#[cfg(target_os = "fuchsia")]
+fn unsupported_fd_passing() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "passing file descriptors isn't available on Fuchsia",
+    )
+}
+
+// Where MSG_CMSG_CLOEXEC exists, received descriptors are close-on-exec from
+// the start. Elsewhere recv_fd sets it right afterwards.
+#[cfg(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn recv_fd_flags() -> c_int {
+    libc::MSG_CMSG_CLOEXEC
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "fuchsia",
+    target_os = "linux",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn recv_fd_flags() -> c_int {
+    0
+}
+
+// With the "mio" feature, pipe ends can be registered with a mio::Poll
+// directly. You'll usually want to put them in non-blocking mode first.
+#[cfg(feature = "mio")]
+macro_rules! impl_mio_source {
+    ($t:ty) => {
+        impl mio::event::Source for $t {
+            fn register(
+                &mut self,
+                registry: &mio::Registry,
+                token: mio::Token,
+                interests: mio::Interest,
+            ) -> io::Result<()> {
+                mio::unix::SourceFd(&self.as_raw_fd()).register(registry, token, interests)
+            }
+
+            fn reregister(
+                &mut self,
+                registry: &mio::Registry,
+                token: mio::Token,
+                interests: mio::Interest,
+            ) -> io::Result<()> {
+                mio::unix::SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)
+            }
+
+            fn deregister(&mut self, registry: &mio::Registry) -> io::Result<()> {
+                mio::unix::SourceFd(&self.as_raw_fd()).deregister(registry)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "mio")]
+impl_mio_source!(PipeReader);
+#[cfg(feature = "mio")]
+impl_mio_source!(PipeWriter);
+#[cfg(feature = "mio")]
+impl_mio_source!(Duplex);
+
+// With the "futures-io" feature, async_io::Async<PipeReader> and friends
+// implement the futures AsyncRead and AsyncWrite traits. That's safe because
+// none of these types ever close or replace their file descriptor while
+// they're borrowed.
+#[cfg(feature = "futures-io")]
+unsafe impl async_io::IoSafe for PipeReader {}
+#[cfg(feature = "futures-io")]
+unsafe impl async_io::IoSafe for PipeWriter {}
+#[cfg(feature = "futures-io")]
+unsafe impl async_io::IoSafe for Duplex {}
+
+#[cfg(not(any(target_os = "fuchsia", target_os = "redox")))]
+pub(crate) fn openpty() -> io::Result<(File, File)> {
+    let mut master = -1;
+    let mut slave = -1;
+    cvt(unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    })?;
+    let master = unsafe { File::from_raw_fd(master) };
+    let slave = unsafe { File::from_raw_fd(slave) };
+    // openpty has no flag for this, so there's a brief window where a child
+    // spawned by another thread could inherit these.
+    set_cloexec(&master)?;
+    set_cloexec(&slave)?;
+    Ok((master, slave))
+}
+
+// Pty ends are plain descriptors here. Windows needs more than that.
+pub(crate) type PtyMasterInner = File;
+pub(crate) type PtySlaveInner = File;
+
+// Reading from the master after the last slave closes fails with EIO on Linux,
+// rather than returning EOF.
+pub(crate) fn read_pty(master: &File, buf: &mut [u8]) -> io::Result<usize> {
+    match read(master, buf) {
+        Err(ref e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+        result => result,
+    }
+}
+
+pub(crate) fn write_pty(master: &File, buf: &[u8]) -> io::Result<usize> {
+    write(master, buf)
+}
+
+#[cfg(not(any(target_os = "fuchsia", target_os = "redox")))]
+pub(crate) fn window_size(file: &File) -> io::Result<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    cvt(unsafe { libc::ioctl(file.as_raw_fd(), libc::TIOCGWINSZ as _, &mut size) })?;
+    Ok((size.ws_row, size.ws_col))
+}
+
+#[cfg(not(any(target_os = "fuchsia", target_os = "redox")))]
+pub(crate) fn set_window_size(file: &File, rows: u16, cols: u16) -> io::Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    cvt(unsafe { libc::ioctl(file.as_raw_fd(), libc::TIOCSWINSZ as _, &size) })?;
+    Ok(())
+}
+
+// Give the child the pty `slave` as its standard streams, and put it in a new
+// session with the slave as its controlling terminal, after the fork and
+// before the exec.
+#[cfg(not(any(target_os = "fuchsia", target_os = "redox")))]
+pub(crate) fn attach_pty(command: &mut Command, slave: &File) -> io::Result<()> {
+    let hook = || {
+        // Only async-signal-safe calls are allowed here, so no allocating. By
+        // the time this runs, the slave has been dup2'd onto stdin, and the
+        // original descriptor is close-on-exec and may be gone already.
+        unsafe {
+            cvt(libc::setsid())?;
+            cvt(libc::ioctl(0, libc::TIOCSCTTY as _, 0))?;
+        }
+        Ok(())
+    };
+    unsafe {
+        command.pre_exec(hook);
+    }
+    command
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave.try_clone()?);
+    Ok(())
+}
+
+#[cfg(any(target_os = "fuchsia", target_os = "redox"))]
+fn unsupported_pty() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pseudoterminals aren't available on Redox or Fuchsia",
+    )
+}
+
+#[cfg(any(target_os = "fuchsia", target_os = "redox"))]
+pub(crate) fn openpty() -> io::Result<(File, File)> {
+    Err(unsupported_pty())
+}
+
+#[cfg(any(target_os = "fuchsia", target_os = "redox"))]
+pub(crate) fn window_size(_file: &File) -> io::Result<(u16, u16)> {
+    Err(unsupported_pty())
+}
+
+#[cfg(any(target_os = "fuchsia", target_os = "redox"))]
+pub(crate) fn set_window_size(_file: &File, _rows: u16, _cols: u16) -> io::Result<()> {
+    Err(unsupported_pty())
+}
+
+#[cfg(any(target_os = "fuchsia", target_os = "redox"))]
+pub(crate) fn attach_pty(_command: &mut Command, _slave: &File) -> io::Result<()> {
+    Err(unsupported_pty())
+}
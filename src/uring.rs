@@ -0,0 +1,222 @@
+extern crate io_uring;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::os::unix::prelude::*;
+
+use self::io_uring::{opcode, squeue, types, IoUring};
+
+use PipeReader;
+use PipeWriter;
+
+// The user_data of the cancellations that Drop submits. Tokens count up from
+// zero, so they'll never get here.
+const CANCEL: u64 = u64::MAX;
+
+/// A batch of pipe reads and writes, submitted to the kernel together through
+/// `io_uring`. This is Linux-only and requires the `io-uring` Cargo feature.
+///
+/// With one thread per pipe, or a `poll` loop, every read and write is its
+/// own syscall. A `PipeRing` queues them up with
+/// [`submit_read`](#method.submit_read) and
+/// [`submit_write`](#method.submit_write), and [`wait`](#method.wait) hands
+/// them all to the kernel at once and returns their results as they finish,
+/// so pumping output from hundreds of children takes a small number of
+/// syscalls per round rather than one per pipe.
+///
+/// The ring borrows the pipes for its lifetime `'a`, so they can't be closed
+/// while the kernel might still be using them. Buffers are moved in and come
+/// back with their [`Completion`]. The pipes should be in blocking mode. The
+/// kernel waits for readiness on its own, and a non-blocking pipe just
+/// completes with `WouldBlock`. Dropping the ring cancels whatever is still
+/// in flight and waits for the kernel to let go of the buffers.
+///
+/// Creating the ring fails on kernels before 5.6, and in sandboxes that
+/// block `io_uring`, so be ready to fall back to ordinary reads and writes.
+///
+/// # Example
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use std::io::prelude::*;
+///
+/// let (reader, mut writer) = os_pipe::pipe()?;
+/// writer.write_all(b"hello")?;
+/// let mut ring = os_pipe::PipeRing::new(8)?;
+/// let token = ring.submit_read(&reader, vec![0; 64])?;
+/// let completion = ring.wait()?.unwrap();
+/// assert_eq!(completion.token, token);
+/// let n = completion.result?;
+/// assert_eq!(&completion.buf[..n], b"hello");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Completion`]: struct.Completion.html
+pub struct PipeRing<'a> {
+    ring: IoUring,
+    next_token: u64,
+    // The buffers of submitted operations, which the kernel owns until they
+    // complete.
+    buffers: HashMap<u64, Vec<u8>>,
+    pipes: PhantomData<&'a ()>,
+}
+
+/// The result of one operation on a [`PipeRing`](struct.PipeRing.html).
+#[derive(Debug)]
+pub struct Completion {
+    /// The token returned when the operation was submitted.
+    pub token: u64,
+    /// The number of bytes read or written, like the return value of `read`
+    /// or `write`. A read of 0 means EOF.
+    pub result: io::Result<usize>,
+    /// The buffer that was submitted, given back. For a read, the data is in
+    /// `buf[..n]`.
+    pub buf: Vec<u8>,
+}
+
+impl<'a> PipeRing<'a> {
+    /// Set up a new ring with room for `entries` operations in each batch.
+    /// More than that can be in flight at once, but submitting past it
+    /// flushes the batch early.
+    pub fn new(entries: u32) -> io::Result<PipeRing<'a>> {
+        Ok(PipeRing {
+            ring: IoUring::new(entries)?,
+            next_token: 0,
+            buffers: HashMap::new(),
+            pipes: PhantomData,
+        })
+    }
+
+    /// Queue a read from `reader` into `buf`, reading at most `buf.len()`
+    /// bytes, and return a token that identifies it in the
+    /// [`Completion`](struct.Completion.html). Nothing reaches the kernel
+    /// until the next [`submit`](#method.submit) or [`wait`](#method.wait).
+    pub fn submit_read(&mut self, reader: &'a PipeReader, mut buf: Vec<u8>) -> io::Result<u64> {
+        let entry = opcode::Read::new(
+            types::Fd(reader.as_raw_fd()),
+            buf.as_mut_ptr(),
+            clamp_len(buf.len()),
+        )
+        .build();
+        self.push(entry, buf)
+    }
+
+    /// Queue a write of `buf` to `writer`, and return a token that identifies
+    /// it in the [`Completion`](struct.Completion.html). As with `write`, the
+    /// result can be short, and it's up to the caller to submit the rest.
+    pub fn submit_write(&mut self, writer: &'a PipeWriter, buf: Vec<u8>) -> io::Result<u64> {
+        let entry = opcode::Write::new(
+            types::Fd(writer.as_raw_fd()),
+            buf.as_ptr(),
+            clamp_len(buf.len()),
+        )
+        .build();
+        self.push(entry, buf)
+    }
+
+    /// Hand everything queued so far to the kernel without waiting.
+    pub fn submit(&mut self) -> io::Result<()> {
+        self.ring.submit()?;
+        Ok(())
+    }
+
+    /// Submit anything queued, then wait for the next operation to finish and
+    /// return it. Completions come back in whatever order they finish, which
+    /// isn't necessarily the order they were submitted. This returns
+    /// `Ok(None)` if nothing is in flight.
+    pub fn wait(&mut self) -> io::Result<Option<Completion>> {
+        loop {
+            if let Some(entry) = self.ring.completion().next() {
+                let token = entry.user_data();
+                if token == CANCEL {
+                    continue;
+                }
+                let buf = self.buffers.remove(&token).expect("unknown token");
+                let result = if entry.result() < 0 {
+                    Err(io::Error::from_raw_os_error(-entry.result()))
+                } else {
+                    Ok(entry.result() as usize)
+                };
+                return Ok(Some(Completion { token, result, buf }));
+            }
+            if self.buffers.is_empty() {
+                return Ok(None);
+            }
+            match self.ring.submit_and_wait(1) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                result => {
+                    result?;
+                }
+            }
+        }
+    }
+
+    /// The number of operations submitted or queued that haven't been
+    /// returned by [`wait`](#method.wait) yet.
+    pub fn in_flight(&self) -> usize {
+        self.buffers.len()
+    }
+
+    fn push(&mut self, entry: squeue::Entry, buf: Vec<u8>) -> io::Result<u64> {
+        let token = self.next_token;
+        let entry = entry.user_data(token);
+        // The buffer's heap allocation doesn't move when the Vec does, so
+        // the pointer in the entry stays good while the map holds it.
+        // Safety: see above, and the borrow of the pipe outlives the ring.
+        while unsafe { self.ring.submission().push(&entry) }.is_err() {
+            // The queue is full. Flush it to make room.
+            self.ring.submit()?;
+        }
+        self.next_token += 1;
+        self.buffers.insert(token, buf);
+        Ok(token)
+    }
+}
+
+impl<'a> Drop for PipeRing<'a> {
+    fn drop(&mut self) {
+        // The kernel can still write into the buffers until each operation
+        // completes, so cancel them all and wait before freeing anything.
+        let tokens: Vec<u64> = self.buffers.keys().cloned().collect();
+        for token in tokens {
+            let entry = opcode::AsyncCancel::new(token).build().user_data(CANCEL);
+            while unsafe { self.ring.submission().push(&entry) }.is_err() {
+                if self.ring.submit().is_err() {
+                    break;
+                }
+            }
+        }
+        while !self.buffers.is_empty() {
+            match self.wait() {
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                // Leak what's left rather than free memory the kernel might
+                // still touch.
+                Err(_) => {
+                    for (_, buf) in self.buffers.drain() {
+                        mem::forget(buf);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> fmt::Debug for PipeRing<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PipeRing")
+            .field("in_flight", &self.buffers.len())
+            .finish()
+    }
+}
+
+// The kernel takes a u32 length, and a short read or write is fine.
+fn clamp_len(len: usize) -> u32 {
+    cmp::min(len, u32::MAX as usize) as u32
+}
@@ -0,0 +1,123 @@
+use std::io;
+use std::io::prelude::*;
+
+use sys;
+use PipeOptions;
+use PipeReader;
+use PipeWriter;
+
+/// The reading end of a self-pipe, returned by
+/// [`wakeup_pipe`](fn.wakeup_pipe.html).
+///
+/// Wait for it alongside your other pipes, with
+/// [`PollEntry::readable`](struct.PollEntry.html#method.readable) on
+/// [`get_ref`](#method.get_ref) or however your event loop likes, and when it
+/// turns readable, call [`drain`](#method.drain) before handling whatever the
+/// wakeup was for.
+#[derive(Debug)]
+pub struct WakeupReader {
+    reader: PipeReader,
+}
+
+impl WakeupReader {
+    /// Read and discard every pending wakeup, and return how many there were.
+    /// This never blocks. Draining first and then checking what happened
+    /// means a wakeup that arrives in between is never lost. At worst it
+    /// causes one extra, empty wakeup. Several notifications can collapse
+    /// into one, so don't count on the number matching.
+    pub fn drain(&self) -> io::Result<usize> {
+        let mut buf = [0; 64];
+        let mut total = 0;
+        loop {
+            match (&self.reader).read(&mut buf) {
+                Ok(0) => return Ok(total),
+                Ok(n) => total += n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(total),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Get a reference to the underlying pipe, for polling. It's in
+    /// non-blocking mode, and should stay that way.
+    pub fn get_ref(&self) -> &PipeReader {
+        &self.reader
+    }
+
+    /// Return the underlying pipe.
+    pub fn into_inner(self) -> PipeReader {
+        self.reader
+    }
+}
+
+/// The writing end of a self-pipe, returned by
+/// [`wakeup_pipe`](fn.wakeup_pipe.html).
+///
+/// A `Waker` can be shared between threads, and
+/// [`notify`](#method.notify) is async-signal-safe, so a Unix signal handler
+/// can call it too. To reach it from a handler, keep it somewhere the handler
+/// can get to without locking, like a `OnceLock`.
+#[derive(Debug)]
+pub struct Waker {
+    writer: PipeWriter,
+}
+
+impl Waker {
+    /// Wake up whoever is waiting on the [`WakeupReader`](struct.WakeupReader.html).
+    /// This writes a single byte and never blocks. It can't fail in any way
+    /// that matters: if the pipe is full, a wakeup is already pending, and if
+    /// the reader is gone, there's nobody to wake. On Unix, it's safe to call
+    /// from a signal handler, and it preserves `errno`.
+    pub fn notify(&self) {
+        sys::notify(&self.writer.0);
+    }
+
+    /// Create another `Waker` for the same pipe, by duplicating it.
+    pub fn try_clone(&self) -> io::Result<Waker> {
+        Ok(Waker {
+            writer: self.writer.try_clone()?,
+        })
+    }
+
+    /// Get a reference to the underlying pipe.
+    pub fn get_ref(&self) -> &PipeWriter {
+        &self.writer
+    }
+}
+
+/// Open a self-pipe, for waking up a thread that's waiting on other pipes,
+/// from another thread or from a signal handler.
+///
+/// Both ends are non-blocking and non-inheritable, so a flood of
+/// notifications can't block the notifier, and the pipe doesn't leak into
+/// child processes. The reader is meant to be polled and then drained.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// use os_pipe::{poll, PollEntry};
+///
+/// // Keep the writer open, so the reader doesn't see EOF.
+/// let (reader, _writer) = os_pipe::pipe()?;
+/// let (wakeup, waker) = os_pipe::wakeup_pipe()?;
+///
+/// let thread = std::thread::spawn(move || waker.notify());
+///
+/// let mut entries = [
+///     PollEntry::readable(&reader),
+///     PollEntry::readable(wakeup.get_ref()),
+/// ];
+/// poll(&mut entries, None)?;
+/// assert!(!entries[0].is_ready());
+/// assert!(entries[1].is_ready());
+/// assert_eq!(wakeup.drain()?, 1);
+/// # thread.join().unwrap();
+/// # Ok(())
+/// # }
+/// ```
+pub fn wakeup_pipe() -> io::Result<(WakeupReader, Waker)> {
+    let (reader, writer) = PipeOptions::new().nonblocking(true).create()?;
+    Ok((WakeupReader { reader }, Waker { writer }))
+}
@@ -0,0 +1,258 @@
+// WASI preview 1 has no pipe, dup, or socketpair calls, and no way to spawn
+// child processes, so most of this file reports Unsupported. What does work is
+// whatever only needs an existing file descriptor: reads and writes, the raw
+// fd conversions, and copying. That's enough for code that's given its pipes
+// by the runtime, like a WASI CLI reading stdin, to use PipeReader and
+// PipeWriter the same way it does natively.
+
+extern crate libc;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::mem::ManuallyDrop;
+use std::net::Shutdown;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use capture::StdStream;
+use Duplex;
+use PipeOptions;
+use PipeReader;
+use PipeWriter;
+use PollEntry;
+
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "not available on WASI")
+}
+
+pub(crate) fn pipe(_options: &PipeOptions) -> io::Result<(PipeReader, PipeWriter)> {
+    Err(unsupported())
+}
+
+pub(crate) fn duplex() -> io::Result<(File, File)> {
+    Err(unsupported())
+}
+
+pub(crate) fn shutdown(_file: &File, _how: Shutdown) -> io::Result<()> {
+    Err(unsupported())
+}
+
+// The standard library reports Unsupported here too, since WASI can't dup.
+pub(crate) fn dup<T: AsRawFd>(wrapper: T) -> io::Result<File> {
+    let fd = wrapper.as_raw_fd();
+    let temp_file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    temp_file.try_clone()
+}
+
+pub(crate) fn is_out_of_descriptors(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EMFILE) | Some(libc::ENFILE))
+}
+
+// WASI runtimes always provide the standard streams, even if only as empty
+// ones.
+pub(crate) fn is_closed_std_stream(_err: &io::Error) -> bool {
+    false
+}
+
+pub(crate) fn read(mut file: &File, buf: &mut [u8]) -> io::Result<usize> {
+    file.read(buf)
+}
+
+pub(crate) fn write(mut file: &File, buf: &[u8]) -> io::Result<usize> {
+    file.write(buf)
+}
+
+// There's no PIPE_BUF to go by, and no way to know what the runtime does with
+// concurrent writes.
+pub(crate) fn write_atomic(_file: &File, _buf: &[u8]) -> io::Result<()> {
+    Err(unsupported())
+}
+
+pub(crate) fn write_timeout(_file: &File, _buf: &[u8], _timeout: Duration) -> io::Result<usize> {
+    Err(unsupported())
+}
+
+// WASI can't create pipes, so there's never a waker to notify.
+pub(crate) fn notify(_file: &File) {}
+
+pub(crate) fn read_vectored(mut file: &File, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+    file.read_vectored(bufs)
+}
+
+pub(crate) fn write_vectored(mut file: &File, bufs: &[io::IoSlice]) -> io::Result<usize> {
+    file.write_vectored(bufs)
+}
+
+pub(crate) fn set_nonblocking(_file: &File, _nonblocking: bool) -> io::Result<()> {
+    Err(unsupported())
+}
+
+// Don't retry on EINTR, for the same reason as on Unix.
+pub(crate) fn close(file: File) -> io::Result<()> {
+    if unsafe { libc::close(file.into_raw_fd()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(crate) fn set_inheritable(_file: &File, _inheritable: bool) -> io::Result<()> {
+    Err(unsupported())
+}
+
+pub(crate) fn raw_value(file: &File) -> u64 {
+    file.as_raw_fd() as u64
+}
+
+pub(crate) unsafe fn from_inherited_raw(_raw: u64) -> io::Result<File> {
+    Err(unsupported())
+}
+
+#[derive(Debug)]
+pub(crate) struct StdCapture(());
+
+impl StdCapture {
+    pub(crate) fn start(_stream: StdStream, _writer: File) -> io::Result<StdCapture> {
+        Err(unsupported())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct NamedPipe(());
+
+impl NamedPipe {
+    pub(crate) fn create(_path: &Path) -> io::Result<NamedPipe> {
+        Err(unsupported())
+    }
+
+    pub(crate) fn accept_reader(self) -> io::Result<File> {
+        Err(unsupported())
+    }
+
+    pub(crate) fn accept_writer(self) -> io::Result<File> {
+        Err(unsupported())
+    }
+}
+
+pub(crate) fn open_named_pipe_reader(_path: &Path) -> io::Result<File> {
+    Err(unsupported())
+}
+
+pub(crate) fn open_named_pipe_writer(_path: &Path) -> io::Result<File> {
+    Err(unsupported())
+}
+
+pub(crate) fn poll(_entries: &mut [PollEntry], _timeout: Option<Duration>) -> io::Result<usize> {
+    Err(unsupported())
+}
+
+pub(crate) fn is_peer_closed(_file: &File) -> io::Result<bool> {
+    Err(unsupported())
+}
+
+pub(crate) fn capacity(_file: &File) -> io::Result<usize> {
+    Err(unsupported())
+}
+
+pub(crate) fn set_capacity(_file: &File, _capacity: usize) -> io::Result<usize> {
+    Err(unsupported())
+}
+
+pub(crate) fn splice(from: &File, to: &File, len: usize) -> io::Result<usize> {
+    ::copy_chunk(from, to, len)
+}
+
+pub(crate) fn send_file(_to: &File, _from: &File, _offset: u64, _len: u64) -> io::Result<u64> {
+    Err(unsupported())
+}
+
+pub(crate) fn copy<R: AsFd, W: AsFd>(from: &R, to: &W) -> io::Result<u64> {
+    let from = ManuallyDrop::new(unsafe { File::from_raw_fd(from.as_fd().as_raw_fd()) });
+    let to = ManuallyDrop::new(unsafe { File::from_raw_fd(to.as_fd().as_raw_fd()) });
+    io::copy(&mut &*from, &mut &*to)
+}
+
+pub(crate) fn tee(_from: &File, _to: &File, _len: usize) -> io::Result<usize> {
+    Err(unsupported())
+}
+
+pub(crate) fn bytes_available(_file: &File) -> io::Result<usize> {
+    Err(unsupported())
+}
+
+pub(crate) fn peek(_file: &File, _buf: &mut [u8]) -> io::Result<usize> {
+    Err(unsupported())
+}
+
+macro_rules! impl_fd_traits {
+    ($t:ident) => {
+        impl IntoRawFd for $t {
+            fn into_raw_fd(self) -> RawFd {
+                self.0.into_raw_fd()
+            }
+        }
+
+        impl AsRawFd for $t {
+            fn as_raw_fd(&self) -> RawFd {
+                self.0.as_raw_fd()
+            }
+        }
+
+        impl AsFd for $t {
+            fn as_fd(&self) -> BorrowedFd<'_> {
+                self.0.as_fd()
+            }
+        }
+
+        impl From<OwnedFd> for $t {
+            fn from(fd: OwnedFd) -> $t {
+                $t(File::from(fd))
+            }
+        }
+
+        impl From<$t> for OwnedFd {
+            fn from(p: $t) -> OwnedFd {
+                p.0.into()
+            }
+        }
+
+        impl FromRawFd for $t {
+            unsafe fn from_raw_fd(fd: RawFd) -> $t {
+                $t(File::from_raw_fd(fd))
+            }
+        }
+    };
+}
+
+impl_fd_traits!(PipeReader);
+impl_fd_traits!(PipeWriter);
+impl_fd_traits!(Duplex);
+
+pub(crate) fn openpty() -> io::Result<(File, File)> {
+    Err(unsupported())
+}
+
+pub(crate) type PtyMasterInner = File;
+pub(crate) type PtySlaveInner = File;
+
+pub(crate) fn read_pty(_master: &File, _buf: &mut [u8]) -> io::Result<usize> {
+    Err(unsupported())
+}
+
+pub(crate) fn write_pty(_master: &File, _buf: &[u8]) -> io::Result<usize> {
+    Err(unsupported())
+}
+
+pub(crate) fn window_size(_file: &File) -> io::Result<(u16, u16)> {
+    Err(unsupported())
+}
+
+pub(crate) fn set_window_size(_file: &File, _rows: u16, _cols: u16) -> io::Result<()> {
+    Err(unsupported())
+}
+
+pub(crate) fn attach_pty(_command: &mut Command, _slave: &File) -> io::Result<()> {
+    Err(unsupported())
+}
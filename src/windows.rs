@@ -1,51 +1,1476 @@
+#[cfg(feature = "mio")]
+extern crate mio;
 extern crate winapi;
 
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::BTreeMap;
+#[cfg(feature = "mio")]
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::{c_void, OsStr, OsString};
+use std::fs;
 use std::fs::File;
 use std::io;
+use std::io::prelude::*;
+use std::mem;
 use std::mem::ManuallyDrop;
+use std::net::Shutdown;
+use std::os::windows::fs::OpenOptionsExt;
 use std::os::windows::prelude::*;
+use std::os::windows::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::process::{Command, ExitStatus};
 use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use self::winapi::shared::minwindef::DWORD;
-use self::winapi::shared::ntdef::{HANDLE, PHANDLE};
-use self::winapi::um::{handleapi, namedpipeapi, processenv, winbase};
+use self::winapi::shared::basetsd::SIZE_T;
+use self::winapi::shared::minwindef::{BOOL, DWORD, FALSE, FARPROC, TRUE};
+use self::winapi::shared::ntdef::{HANDLE, HRESULT, PHANDLE};
+use self::winapi::shared::sddl;
+use self::winapi::shared::winerror;
+use self::winapi::um::minwinbase::{OVERLAPPED, SECURITY_ATTRIBUTES};
+use self::winapi::um::processthreadsapi::{PROCESS_INFORMATION, PROC_THREAD_ATTRIBUTE_LIST};
+use self::winapi::um::winnt::{
+    DUPLICATE_CLOSE_SOURCE, DUPLICATE_SAME_ACCESS, FILE_READ_ATTRIBUTES, FILE_WRITE_ATTRIBUTES,
+    GENERIC_READ, GENERIC_WRITE, PSECURITY_DESCRIPTOR,
+};
+use self::winapi::um::{
+    errhandlingapi, fileapi, handleapi, ioapiset, libloaderapi, namedpipeapi, processenv,
+    processthreadsapi, synchapi, winbase,
+};
 
+use capture::StdStream;
+use Duplex;
+use HandleList;
+use PipeOptions;
 use PipeReader;
 use PipeWriter;
+use PollEntry;
 
-pub(crate) fn pipe() -> io::Result<(PipeReader, PipeWriter)> {
+pub(crate) fn pipe(options: &PipeOptions) -> io::Result<(PipeReader, PipeWriter)> {
+    // CreatePipe treats nSize as a hint, and 0 means the system default.
+    let size = cmp::min(options.capacity.unwrap_or(0), DWORD::MAX as usize) as DWORD;
+    let descriptor = match options.security_descriptor {
+        Some(ref sddl) => Some(SecurityDescriptor::from_sddl(sddl)?),
+        None => None,
+    };
+    let (reader, writer) = if options.overlapped || options.message_mode {
+        // CreatePipe can't do either of these, so use a named pipe.
+        let flags = if options.overlapped {
+            winbase::FILE_FLAG_OVERLAPPED
+        } else {
+            0
+        };
+        let pipe_mode = if options.message_mode {
+            winbase::PIPE_TYPE_MESSAGE | winbase::PIPE_READMODE_MESSAGE
+        } else {
+            winbase::PIPE_TYPE_BYTE | winbase::PIPE_READMODE_BYTE
+        };
+        let (server, client) = named_pipe_pair(
+            winbase::PIPE_ACCESS_INBOUND | flags,
+            pipe_mode,
+            GENERIC_WRITE,
+            flags,
+            size,
+            descriptor.as_ref(),
+        )?;
+        // Named pipe handles can't be made inheritable as they're created,
+        // because OpenOptions doesn't take SECURITY_ATTRIBUTES.
+        if options.inheritable {
+            set_inheritable(&server, true)?;
+            set_inheritable(&client, true)?;
+        }
+        (PipeReader(server), PipeWriter(client))
+    } else {
+        create_pipe(size, options.inheritable, descriptor.as_ref())?
+    };
+    // CreatePipe can't do this part, but no one else has seen these handles
+    // yet, so it's just as good.
+    if options.nonblocking {
+        set_nonblocking(&reader.0, true)?;
+        set_nonblocking(&writer.0, true)?;
+    }
+    Ok((reader, writer))
+}
+
+fn create_pipe(
+    size: DWORD,
+    inheritable: bool,
+    descriptor: Option<&SecurityDescriptor>,
+) -> io::Result<(PipeReader, PipeWriter)> {
     let mut read_pipe: HANDLE = ptr::null_mut();
     let mut write_pipe: HANDLE = ptr::null_mut();
+    let mut security_attributes = security_attributes(descriptor, inheritable);
 
     let ret = unsafe {
-        // TODO: These pipes do not support IOCP. We might want to emulate anonymous pipes with
-        // CreateNamedPipe, as Rust's stdlib does.
         namedpipeapi::CreatePipe(
             &mut read_pipe as PHANDLE,
             &mut write_pipe as PHANDLE,
-            ptr::null_mut(),
-            0,
+            &mut security_attributes,
+            size,
         )
     };
 
     if ret == 0 {
-        Err(io::Error::last_os_error())
-    } else {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe {
+        Ok((
+            PipeReader::from_raw_handle(read_pipe as _),
+            PipeWriter::from_raw_handle(write_pipe as _),
+        ))
+    }
+}
+
+pub(crate) fn duplex() -> io::Result<(File, File)> {
+    named_pipe_pair(
+        winbase::PIPE_ACCESS_DUPLEX,
+        winbase::PIPE_TYPE_BYTE | winbase::PIPE_READMODE_BYTE,
+        GENERIC_READ | GENERIC_WRITE,
+        0,
+        0,
+        None,
+    )
+}
+
+/// A security descriptor parsed from an SDDL string, like `D:P(A;;GA;;;OW)`,
+/// which gives the owner full access and no one else anything. The default
+/// descriptor for a pipe comes from the creator's token, and usually grants
+/// read access to Everyone and the anonymous account too.
+pub(crate) struct SecurityDescriptor(PSECURITY_DESCRIPTOR);
+
+impl SecurityDescriptor {
+    pub(crate) fn from_sddl(sddl: &str) -> io::Result<SecurityDescriptor> {
+        if sddl.contains('\0') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "security descriptor contains a nul byte",
+            ));
+        }
+        let wide: Vec<u16> = sddl.encode_utf16().chain(Some(0)).collect();
+        let mut descriptor: PSECURITY_DESCRIPTOR = ptr::null_mut();
+        let ret = unsafe {
+            sddl::ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                wide.as_ptr(),
+                sddl::SDDL_REVISION_1 as DWORD,
+                &mut descriptor,
+                ptr::null_mut(),
+            )
+        };
+        if ret == 0 {
+            let err = io::Error::last_os_error();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid security descriptor {:?}: {}", sddl, err),
+            ));
+        }
+        Ok(SecurityDescriptor(descriptor))
+    }
+}
+
+impl Drop for SecurityDescriptor {
+    fn drop(&mut self) {
         unsafe {
-            Ok((
-                PipeReader::from_raw_handle(read_pipe as _),
-                PipeWriter::from_raw_handle(write_pipe as _),
-            ))
+            winbase::LocalFree(self.0);
         }
     }
 }
 
+// A null descriptor means the default one.
+fn security_attributes(
+    descriptor: Option<&SecurityDescriptor>,
+    inheritable: bool,
+) -> SECURITY_ATTRIBUTES {
+    SECURITY_ATTRIBUTES {
+        nLength: mem::size_of::<SECURITY_ATTRIBUTES>() as DWORD,
+        lpSecurityDescriptor: descriptor.map_or(ptr::null_mut(), |d| d.0),
+        bInheritHandle: inheritable as BOOL,
+    }
+}
+
+// A named pipe has no way to close just one direction.
+pub(crate) fn shutdown(_file: &File, _how: Shutdown) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Windows pipes can't be shut down in one direction",
+    ))
+}
+
+/// Make a connected pair of handles out of a named pipe with a unique name.
+/// This is what the standard library does internally when it needs features
+/// that CreatePipe doesn't support. `open_mode` and `pipe_mode` go to
+/// CreateNamedPipe for the first handle, and `client_access` goes to
+/// CreateFile for the second, along with `client_flags`. `size` is the
+/// buffer size hint, where 0 means the system default, and `descriptor` goes
+/// on the pipe, where None means the default.
+fn named_pipe_pair(
+    open_mode: DWORD,
+    pipe_mode: DWORD,
+    client_access: DWORD,
+    client_flags: DWORD,
+    size: DWORD,
+    descriptor: Option<&SecurityDescriptor>,
+) -> io::Result<(File, File)> {
+    let name = unique_pipe_name();
+    let wide_name = to_wide(Path::new(&name));
+    let mut security_attributes = security_attributes(descriptor, false);
+    let server = unsafe {
+        namedpipeapi::CreateNamedPipeW(
+            wide_name.as_ptr(),
+            // The name is unique to this process, so if something else has
+            // already taken it, we've been squatted on. Fail in that case.
+            open_mode | winbase::FILE_FLAG_FIRST_PIPE_INSTANCE,
+            pipe_mode | winbase::PIPE_WAIT | winbase::PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            size,
+            size,
+            0,
+            &mut security_attributes,
+        )
+    };
+    if server == handleapi::INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    let server = unsafe { File::from_raw_handle(server as _) };
+    // Connecting right away means ConnectNamedPipe isn't needed. Ask for the
+    // attributes access bits so that set_nonblocking works.
+    let client = fs::OpenOptions::new()
+        .access_mode(client_access | FILE_READ_ATTRIBUTES | FILE_WRITE_ATTRIBUTES)
+        .custom_flags(client_flags)
+        .open(&name)?;
+    Ok((server, client))
+}
+
+fn unique_pipe_name() -> String {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    format!(
+        r"\\.\pipe\os_pipe-{}-{}",
+        process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
 pub(crate) fn dup<T: AsRawHandle>(wrapper: T) -> io::Result<File> {
     let handle = wrapper.as_raw_handle();
+    // A process without a console, like a GUI app, has null standard handles.
+    // INVALID_HANDLE_VALUE is worse, because DuplicateHandle would take it to
+    // mean the current process.
+    if handle.is_null() || handle as HANDLE == handleapi::INVALID_HANDLE_VALUE {
+        return Err(io::Error::from_raw_os_error(
+            winerror::ERROR_INVALID_HANDLE as i32,
+        ));
+    }
     let temp_file = ManuallyDrop::new(unsafe { File::from_raw_handle(handle) });
     temp_file.try_clone()
 }
 
+// Handles don't have a fixed limit, but a process that runs out of them, or
+// out of the kernel memory behind them, gets one of these.
+pub(crate) fn is_out_of_descriptors(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(code) => {
+            code == winerror::ERROR_TOO_MANY_OPEN_FILES as i32
+                || code == winerror::ERROR_NO_SYSTEM_RESOURCES as i32
+        }
+        None => false,
+    }
+}
+
+// The standard handles are null or stale in a process that doesn't have them.
+pub(crate) fn is_closed_std_stream(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(winerror::ERROR_INVALID_HANDLE as i32)
+}
+
+// Do all pipe reads and writes with an OVERLAPPED struct, so that they work on
+// pipes opened with FILE_FLAG_OVERLAPPED (see PipeOptions::overlapped) as well
+// as ordinary ones. The standard library aborts the process if an overlapped
+// read doesn't complete immediately. Pipes ignore the offset fields, so this
+// is harmless for synchronous pipes, which complete before returning. Files
+// don't: a zeroed OVERLAPPED reads and writes at offset 0 every time, so
+// anything that isn't a pipe goes through the standard library instead (see
+// is_pipe).
+fn overlapped_io<F>(file: &File, op: F) -> io::Result<usize>
+where
+    F: FnOnce(HANDLE, *mut OVERLAPPED) -> BOOL,
+{
+    let handle = file.as_raw_handle() as HANDLE;
+    let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+    // Setting the low bit of the event handle keeps the completion from being
+    // posted to an I/O completion port the pipe might be associated with (see
+    // Readiness::associate_iocp). Windows ignores that bit otherwise.
+    overlapped.hEvent = (io_event()? as usize | 1) as HANDLE;
+    // A message pipe read with a short buffer fails with ERROR_MORE_DATA, but
+    // it still reads what fits, and the rest comes back on the next read.
+    let is_partial = |err: &io::Error| err.raw_os_error() == Some(winerror::ERROR_MORE_DATA as i32);
+    if op(handle, &mut overlapped) == 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(winerror::ERROR_IO_PENDING as i32) && !is_partial(&err) {
+            return Err(err);
+        }
+    }
+    let mut transferred: DWORD = 0;
+    let ret =
+        unsafe { ioapiset::GetOverlappedResult(handle, &mut overlapped, &mut transferred, TRUE) };
+    if ret == 0 {
+        let err = io::Error::last_os_error();
+        if !is_partial(&err) {
+            return Err(err);
+        }
+    }
+    Ok(transferred as usize)
+}
+
+struct Event(HANDLE);
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        unsafe {
+            handleapi::CloseHandle(self.0);
+        }
+    }
+}
+
+thread_local! {
+    static IO_EVENT: RefCell<Option<Event>> = const { RefCell::new(None) };
+}
+
+// Each thread has at most one operation in flight, so it can reuse one event.
+// ReadFile and WriteFile reset it before they start.
+fn io_event() -> io::Result<HANDLE> {
+    IO_EVENT.with(|cell| {
+        let mut event = cell.borrow_mut();
+        if event.is_none() {
+            let handle =
+                unsafe { synchapi::CreateEventW(ptr::null_mut(), TRUE, FALSE, ptr::null()) };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            *event = Some(Event(handle));
+        }
+        Ok(event.as_ref().unwrap().0)
+    })
+}
+
+// The most that a single ReadFile or WriteFile gets. A DWORD can't count past
+// 4 GiB anyway, but well before that, a blocking pipe write has to lock the
+// whole buffer in memory until the reader takes it, and large enough writes
+// fail with ERROR_NO_SYSTEM_RESOURCES or ERROR_WORKING_SET_QUOTA. Returning a
+// short count instead is normal for Write, and write_all keeps going. In a
+// message pipe, that would quietly turn one message into several, so a larger
+// write fails there instead (see check_message_size).
+const MAX_IO_SIZE: usize = 1 << 26;
+
+fn is_message_pipe(file: &File) -> bool {
+    let mut flags: DWORD = 0;
+    let ret = unsafe {
+        namedpipeapi::GetNamedPipeInfo(
+            file.as_raw_handle() as HANDLE,
+            &mut flags,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    ret != 0 && flags & winbase::PIPE_TYPE_MESSAGE != 0
+}
+
+// Only asks the pipe when the write is too big, so ordinary writes don't pay
+// for the extra call.
+fn check_message_size(file: &File, len: usize) -> io::Result<()> {
+    if len > MAX_IO_SIZE && is_message_pipe(file) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "message larger than 64 MiB",
+        ));
+    }
+    Ok(())
+}
+
+// A pipe end can wrap any handle (see from_raw_handle, dup_reader and
+// dup_stdout), and a file or a console is synchronous and has a position that
+// overlapped_io would ignore.
+fn is_pipe(file: &File) -> bool {
+    unsafe { fileapi::GetFileType(file.as_raw_handle() as HANDLE) == winbase::FILE_TYPE_PIPE }
+}
+
+pub(crate) fn read(file: &File, buf: &mut [u8]) -> io::Result<usize> {
+    let len = cmp::min(buf.len(), MAX_IO_SIZE);
+    if !is_pipe(file) {
+        let mut file_ref = file;
+        return file_ref.read(&mut buf[..len]);
+    }
+    let len = len as DWORD;
+    let result = overlapped_io(file, |handle, overlapped| unsafe {
+        fileapi::ReadFile(
+            handle,
+            buf.as_mut_ptr() as _,
+            len,
+            ptr::null_mut(),
+            overlapped,
+        )
+    });
+    match result {
+        // A PIPE_NOWAIT pipe with nothing in it fails reads with ERROR_NO_DATA.
+        // Real EOF shows up as ERROR_BROKEN_PIPE instead.
+        Err(ref e) if e.raw_os_error() == Some(winerror::ERROR_NO_DATA as i32) => {
+            Err(io::Error::from(io::ErrorKind::WouldBlock))
+        }
+        Err(ref e) if e.raw_os_error() == Some(winerror::ERROR_BROKEN_PIPE as i32) => Ok(0),
+        result => result,
+    }
+}
+
+pub(crate) fn write(file: &File, buf: &[u8]) -> io::Result<usize> {
+    let len = cmp::min(buf.len(), MAX_IO_SIZE);
+    if !is_pipe(file) {
+        let mut file_ref = file;
+        return file_ref.write(&buf[..len]);
+    }
+    check_message_size(file, buf.len())?;
+    let len = len as DWORD;
+    let result = overlapped_io(file, |handle, overlapped| unsafe {
+        fileapi::WriteFile(handle, buf.as_ptr() as _, len, ptr::null_mut(), overlapped)
+    });
+    match result {
+        // A PIPE_NOWAIT pipe with a full buffer "succeeds" without writing
+        // anything. Blocking pipes never do that.
+        Ok(0) if !buf.is_empty() => Err(io::Error::from(io::ErrorKind::WouldBlock)),
+        result => result,
+    }
+}
+
+// Each WriteFile to a pipe is queued whole, no matter its size. Only one
+// that write() would split, or a PIPE_NOWAIT pipe without enough room, can
+// write part of the buffer.
+pub(crate) fn write_atomic(file: &File, buf: &[u8]) -> io::Result<()> {
+    if buf.len() > MAX_IO_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "write too large to be atomic",
+        ));
+    }
+    ::write_once(file, buf)
+}
+
+// There are no signal handlers on Windows. Console control handlers run on a
+// thread of their own, where an ordinary write is fine. Errors are ignored,
+// since a full pipe already has a wakeup pending and a closed one has nobody
+// to wake.
+pub(crate) fn notify(file: &File) {
+    let _ = write(file, &[1]);
+}
+
+// Windows has no vectored IO for pipes. (ReadFileScatter and WriteFileGather
+// only work on unbuffered files.) Gather the slices into one buffer instead, so
+// that a vectored write is still one WriteFile, which in a message pipe means
+// one message, and a vectored read is one ReadFile spread across the slices.
+// Up to this much of that buffer is on the stack.
+const VECTORED_STACK_SIZE: usize = 8192;
+
+pub(crate) fn read_vectored(file: &File, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+    let total = cmp::min(bufs.iter().map(|b| b.len()).sum(), MAX_IO_SIZE);
+    if let Some(buf) = bufs.iter_mut().find(|b| !b.is_empty()) {
+        if buf.len() >= total {
+            return read(file, buf);
+        }
+    }
+    let mut stack_buf = [0; VECTORED_STACK_SIZE];
+    let mut heap_buf = Vec::new();
+    let gathered = if total <= VECTORED_STACK_SIZE {
+        &mut stack_buf[..total]
+    } else {
+        heap_buf.resize(total, 0);
+        &mut heap_buf[..]
+    };
+    let n = read(file, gathered)?;
+    let mut rest = &gathered[..n];
+    for buf in bufs.iter_mut() {
+        if rest.is_empty() {
+            break;
+        }
+        let len = cmp::min(buf.len(), rest.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        rest = &rest[len..];
+    }
+    Ok(n)
+}
+
+pub(crate) fn write_vectored(file: &File, bufs: &[io::IoSlice]) -> io::Result<usize> {
+    let total = bufs.iter().map(|b| b.len()).sum();
+    check_message_size(file, total)?;
+    let total = cmp::min(total, MAX_IO_SIZE);
+    match bufs.iter().find(|b| !b.is_empty()) {
+        Some(buf) if buf.len() >= total => return write(file, buf),
+        None => return write(file, &[]),
+        Some(_) => {}
+    }
+    let mut stack_buf = [0; VECTORED_STACK_SIZE];
+    let mut heap_buf = Vec::new();
+    let gathered = if total <= VECTORED_STACK_SIZE {
+        &mut stack_buf[..total]
+    } else {
+        heap_buf.resize(total, 0);
+        &mut heap_buf[..]
+    };
+    let mut filled = 0;
+    for buf in bufs {
+        let len = cmp::min(buf.len(), total - filled);
+        gathered[filled..filled + len].copy_from_slice(&buf[..len]);
+        filled += len;
+    }
+    write(file, gathered)
+}
+
+// There's no way to wait for room in a pipe, so switch it to PIPE_NOWAIT and
+// retry with a backoff, like poll does for readers.
+pub(crate) fn write_timeout(file: &File, buf: &[u8], timeout: Duration) -> io::Result<usize> {
+    let deadline = Instant::now() + timeout;
+    let was_nonblocking = is_nonblocking(file)?;
+    if !was_nonblocking {
+        set_nonblocking(file, true)?;
+    }
+    let mut written = 0;
+    let mut sleep = Duration::from_millis(1);
+    let result = loop {
+        match write(file, &buf[written..]) {
+            Ok(n) if n > 0 => {
+                written += n;
+                sleep = Duration::from_millis(1);
+            }
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => break Err(e),
+        }
+        let now = Instant::now();
+        if written == buf.len() || now >= deadline {
+            break Ok(written);
+        }
+        thread::sleep(cmp::min(sleep, deadline - now));
+        sleep = cmp::min(sleep * 2, MAX_POLL_SLEEP);
+    };
+    if was_nonblocking {
+        return result;
+    }
+    // Some of buf may be in the pipe already, and the caller needs to know
+    // that even if the pipe is stuck in PIPE_NOWAIT now. Only report a failed
+    // restore when there's nothing else to report.
+    match (result, set_nonblocking(file, false)) {
+        (Ok(0), Err(e)) => Err(e),
+        (result, _) => result,
+    }
+}
+
+fn handle_state(file: &File) -> io::Result<DWORD> {
+    let mut state: DWORD = 0;
+    let ret = unsafe {
+        namedpipeapi::GetNamedPipeHandleStateW(
+            file.as_raw_handle() as HANDLE,
+            &mut state,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            0,
+        )
+    };
+    if ret == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(state)
+}
+
+fn is_nonblocking(file: &File) -> io::Result<bool> {
+    Ok(handle_state(file)? & winbase::PIPE_NOWAIT != 0)
+}
+
+pub(crate) fn set_nonblocking(file: &File, nonblocking: bool) -> io::Result<()> {
+    let handle = file.as_raw_handle() as HANDLE;
+    // Preserve the read mode of the pipe and only flip the wait flag.
+    let state = handle_state(file)?;
+    let mut mode = if nonblocking {
+        state | winbase::PIPE_NOWAIT
+    } else {
+        state & !winbase::PIPE_NOWAIT
+    };
+    let ret = unsafe {
+        namedpipeapi::SetNamedPipeHandleState(handle, &mut mode, ptr::null_mut(), ptr::null_mut())
+    };
+    if ret == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn close(file: File) -> io::Result<()> {
+    let handle = file.into_raw_handle();
+    if unsafe { handleapi::CloseHandle(handle as HANDLE) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(crate) fn set_inheritable(file: &File, inheritable: bool) -> io::Result<()> {
+    set_handle_inheritable(file.as_raw_handle(), inheritable)
+}
+
+pub(crate) fn set_handle_inheritable(handle: RawHandle, inheritable: bool) -> io::Result<()> {
+    let flags = if inheritable {
+        winbase::HANDLE_FLAG_INHERIT
+    } else {
+        0
+    };
+    let ret = unsafe {
+        handleapi::SetHandleInformation(handle as HANDLE, winbase::HANDLE_FLAG_INHERIT, flags)
+    };
+    if ret == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(crate) fn raw_value(file: &File) -> u64 {
+    file.as_raw_handle() as usize as u64
+}
+
+pub(crate) unsafe fn from_inherited_raw(raw: u64) -> io::Result<File> {
+    let handle = raw as usize as HANDLE;
+    // FILE_TYPE_UNKNOWN is also how GetFileType reports an invalid handle, and
+    // only the last error tells them apart. GetFileType doesn't clear it on
+    // success, so clear it first rather than trust whatever failed before.
+    errhandlingapi::SetLastError(winerror::NO_ERROR);
+    let file_type = fileapi::GetFileType(handle);
+    if file_type != winbase::FILE_TYPE_PIPE {
+        let err = io::Error::last_os_error();
+        if file_type == winbase::FILE_TYPE_UNKNOWN
+            && err.raw_os_error() != Some(winerror::NO_ERROR as i32)
+        {
+            return Err(err);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "inherited handle is not a pipe",
+        ));
+    }
+    let file = File::from_raw_handle(handle as _);
+    set_inheritable(&file, false)?;
+    Ok(file)
+}
+
+#[derive(Debug)]
+pub(crate) struct StdCapture {
+    std_handle: DWORD,
+    saved: HANDLE,
+    // Unlike with dup2 on Unix, the standard handle isn't a copy, so the
+    // writer has to stay open until it's swapped back out.
+    _writer: File,
+}
+
+impl StdCapture {
+    pub(crate) fn start(stream: StdStream, writer: File) -> io::Result<StdCapture> {
+        let std_handle = match stream {
+            StdStream::Stdout => winbase::STD_OUTPUT_HANDLE,
+            StdStream::Stderr => winbase::STD_ERROR_HANDLE,
+        };
+        // A null handle just means there's no stdout, and that's fine to put
+        // back later.
+        let saved = unsafe { processenv::GetStdHandle(std_handle) };
+        if saved == handleapi::INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        let ret = unsafe { processenv::SetStdHandle(std_handle, writer.as_raw_handle() as HANDLE) };
+        if ret == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(StdCapture {
+            std_handle,
+            saved,
+            _writer: writer,
+        })
+    }
+}
+
+impl Drop for StdCapture {
+    fn drop(&mut self) {
+        unsafe {
+            processenv::SetStdHandle(self.std_handle, self.saved);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct NamedPipe {
+    handle: File,
+}
+
+impl NamedPipe {
+    pub(crate) fn create(path: &Path) -> io::Result<NamedPipe> {
+        NamedPipe::create_secured(path, None)
+    }
+
+    pub(crate) fn create_secured(path: &Path, sddl: Option<&str>) -> io::Result<NamedPipe> {
+        let name = to_wide(path);
+        let descriptor = match sddl {
+            Some(sddl) => Some(SecurityDescriptor::from_sddl(sddl)?),
+            None => None,
+        };
+        let mut security_attributes = security_attributes(descriptor.as_ref(), false);
+        let handle = unsafe {
+            namedpipeapi::CreateNamedPipeW(
+                name.as_ptr(),
+                // The server end is duplex, so that the client can choose
+                // which direction it wants when it opens the pipe. Failing if
+                // the name is taken matches mkfifo on Unix.
+                winbase::PIPE_ACCESS_DUPLEX | winbase::FILE_FLAG_FIRST_PIPE_INSTANCE,
+                winbase::PIPE_TYPE_BYTE
+                    | winbase::PIPE_READMODE_BYTE
+                    | winbase::PIPE_WAIT
+                    | winbase::PIPE_REJECT_REMOTE_CLIENTS,
+                1,
+                0,
+                0,
+                0,
+                &mut security_attributes,
+            )
+        };
+        if handle == handleapi::INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(NamedPipe {
+            handle: unsafe { File::from_raw_handle(handle as _) },
+        })
+    }
+
+    fn connect(self) -> io::Result<File> {
+        let ret = unsafe {
+            namedpipeapi::ConnectNamedPipe(self.handle.as_raw_handle() as HANDLE, ptr::null_mut())
+        };
+        if ret == 0 {
+            let err = io::Error::last_os_error();
+            // The client might've connected between CreateNamedPipe and
+            // ConnectNamedPipe. That's a success.
+            if err.raw_os_error() != Some(winerror::ERROR_PIPE_CONNECTED as i32) {
+                return Err(err);
+            }
+        }
+        Ok(self.handle)
+    }
+
+    pub(crate) fn accept_reader(self) -> io::Result<File> {
+        self.connect()
+    }
+
+    pub(crate) fn accept_writer(self) -> io::Result<File> {
+        self.connect()
+    }
+}
+
+// There's no /dev/fd on Windows, so serve the pipe under a fresh name, and
+// once the child connects, copy between that and the original on a thread.
+// Errors on the thread have nowhere to go. The usual one is the child closing
+// its end early, which is fine.
+pub(crate) fn child_path(
+    file: &File,
+    _command: &mut Command,
+    reading: bool,
+) -> io::Result<PathBuf> {
+    let name = PathBuf::from(unique_pipe_name());
+    let server = NamedPipe::create(&name)?;
+    let original = file.try_clone()?;
+    thread::Builder::new()
+        .name("os_pipe child path".into())
+        .spawn(move || -> io::Result<u64> {
+            let connected = server.connect()?;
+            if reading {
+                io::copy(&mut AnyHandle(&original), &mut AnyHandle(&connected))
+            } else {
+                io::copy(&mut AnyHandle(&connected), &mut AnyHandle(&original))
+            }
+        })?;
+    Ok(name)
+}
+
+// Not in winapi. This is ProcThreadAttributeValue(2, FALSE, TRUE, FALSE).
+const PROC_THREAD_ATTRIBUTE_HANDLE_LIST: usize = 0x0002_0002;
+
+pub(crate) fn spawn_with_handle_list(list: &HandleList) -> io::Result<(OwnedHandle, u32)> {
+    let stdio = [list.stdin, list.stdout, list.stderr];
+    // Everything the child inherits is a fresh, inheritable duplicate, so the
+    // caller's handles are never inheritable themselves.
+    let mut duplicates = Vec::new();
+    let std_handles = [
+        winbase::STD_INPUT_HANDLE,
+        winbase::STD_OUTPUT_HANDLE,
+        winbase::STD_ERROR_HANDLE,
+    ];
+    let console = list.pty.map(|slave| (slave.0).0.handle);
+    let mut child_stdio = [ptr::null_mut(); 3];
+    for (i, handle) in stdio.iter().enumerate() {
+        let raw = match *handle {
+            Some(handle) => handle.as_raw_handle() as HANDLE,
+            // In a pseudoconsole, streams that aren't set belong to the
+            // console. Passing the parent's would send them around it.
+            None if console.is_some() => {
+                child_stdio[i] = handleapi::INVALID_HANDLE_VALUE;
+                continue;
+            }
+            None => unsafe { processenv::GetStdHandle(std_handles[i]) },
+        };
+        // Like Stdio::inherit, leave out standard handles the parent doesn't
+        // have.
+        if raw.is_null() || raw == handleapi::INVALID_HANDLE_VALUE {
+            continue;
+        }
+        let duplicate = duplicate_inheritable(raw)?;
+        child_stdio[i] = duplicate.as_raw_handle() as HANDLE;
+        duplicates.push(duplicate);
+    }
+    let mut passed = Vec::new();
+    for (key, handle) in &list.pipes {
+        let duplicate = duplicate_inheritable(handle.as_raw_handle() as HANDLE)?;
+        let value = OsString::from((duplicate.as_raw_handle() as usize).to_string());
+        passed.push((key.clone(), value));
+        duplicates.push(duplicate);
+    }
+    let handles: Vec<HANDLE> = duplicates
+        .iter()
+        .map(|handle| handle.as_raw_handle() as HANDLE)
+        .collect();
+
+    let mut command_line = command_line(list)?;
+    let mut environment = environment_block(list, &passed)?;
+    let current_dir = list.current_dir.as_deref().map(to_wide);
+    let mut attributes = ProcThreadAttributeList::new(2)?;
+    let mut startup_info: winbase::STARTUPINFOEXW = unsafe { mem::zeroed() };
+    startup_info.StartupInfo.cb = mem::size_of::<winbase::STARTUPINFOEXW>() as DWORD;
+    startup_info.StartupInfo.dwFlags = winbase::STARTF_USESTDHANDLES;
+    startup_info.StartupInfo.hStdInput = child_stdio[0];
+    startup_info.StartupInfo.hStdOutput = child_stdio[1];
+    startup_info.StartupInfo.hStdError = child_stdio[2];
+    // An empty list isn't allowed, but then there's nothing to inherit.
+    if !handles.is_empty() {
+        let ret = unsafe {
+            processthreadsapi::UpdateProcThreadAttribute(
+                attributes.as_mut_ptr(),
+                0,
+                PROC_THREAD_ATTRIBUTE_HANDLE_LIST,
+                handles.as_ptr() as _,
+                (handles.len() * mem::size_of::<HANDLE>()) as SIZE_T,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if ret == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        startup_info.lpAttributeList = attributes.as_mut_ptr();
+    }
+    if let Some(console) = console {
+        // The value is the HPCON itself, not a pointer to one.
+        let ret = unsafe {
+            processthreadsapi::UpdateProcThreadAttribute(
+                attributes.as_mut_ptr(),
+                0,
+                PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE,
+                console as _,
+                mem::size_of::<Hpcon>() as SIZE_T,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if ret == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        startup_info.lpAttributeList = attributes.as_mut_ptr();
+    }
+    let mut info: PROCESS_INFORMATION = unsafe { mem::zeroed() };
+    let ret = unsafe {
+        processthreadsapi::CreateProcessW(
+            ptr::null(),
+            command_line.as_mut_ptr(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            !handles.is_empty() as BOOL,
+            list.creation_flags
+                | winbase::EXTENDED_STARTUPINFO_PRESENT
+                | winbase::CREATE_UNICODE_ENVIRONMENT,
+            environment.as_mut_ptr() as _,
+            current_dir.as_ref().map_or(ptr::null(), |dir| dir.as_ptr()),
+            &mut startup_info.StartupInfo,
+            &mut info,
+        )
+    };
+    if ret == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe {
+        handleapi::CloseHandle(info.hThread);
+        Ok((
+            OwnedHandle::from_raw_handle(info.hProcess as _),
+            info.dwProcessId,
+        ))
+    }
+}
+
+fn duplicate_inheritable(handle: HANDLE) -> io::Result<OwnedHandle> {
+    let mut duplicate: HANDLE = ptr::null_mut();
+    let ret = unsafe {
+        handleapi::DuplicateHandle(
+            processthreadsapi::GetCurrentProcess(),
+            handle,
+            processthreadsapi::GetCurrentProcess(),
+            &mut duplicate,
+            0,
+            TRUE,
+            DUPLICATE_SAME_ACCESS,
+        )
+    };
+    if ret == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedHandle::from_raw_handle(duplicate as _) })
+}
+
+struct ProcThreadAttributeList(Vec<usize>);
+
+impl ProcThreadAttributeList {
+    fn new(count: DWORD) -> io::Result<ProcThreadAttributeList> {
+        // The first call only reports the size, and fails while doing it.
+        let mut size: SIZE_T = 0;
+        unsafe {
+            processthreadsapi::InitializeProcThreadAttributeList(
+                ptr::null_mut(),
+                count,
+                0,
+                &mut size,
+            );
+        }
+        // Vec<usize> keeps it pointer-aligned.
+        let words = size.div_ceil(mem::size_of::<usize>());
+        let mut buf = vec![0usize; words];
+        let ret = unsafe {
+            processthreadsapi::InitializeProcThreadAttributeList(
+                buf.as_mut_ptr() as _,
+                count,
+                0,
+                &mut size,
+            )
+        };
+        if ret == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ProcThreadAttributeList(buf))
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut PROC_THREAD_ATTRIBUTE_LIST {
+        self.0.as_mut_ptr() as _
+    }
+}
+
+impl Drop for ProcThreadAttributeList {
+    fn drop(&mut self) {
+        unsafe {
+            processthreadsapi::DeleteProcThreadAttributeList(self.as_mut_ptr());
+        }
+    }
+}
+
+// Quote the program and arguments the way the Microsoft C runtime parses them
+// back, which is also what the standard library does. The program is always
+// quoted, and it can't contain quotes, because CreateProcess takes it
+// literally up to the closing one.
+fn command_line(list: &HandleList) -> io::Result<Vec<u16>> {
+    let program = &list.program;
+    if program.encode_wide().any(|c| c == '"' as u16) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "program name contains a quote",
+        ));
+    }
+    let mut line = Vec::new();
+    append_arg(&mut line, program, true)?;
+    for arg in &list.args {
+        line.push(' ' as u16);
+        append_arg(&mut line, arg, false)?;
+    }
+    line.push(0);
+    Ok(line)
+}
+
+fn append_arg(line: &mut Vec<u16>, arg: &OsStr, force_quotes: bool) -> io::Result<()> {
+    let quote = force_quotes
+        || arg.is_empty()
+        || arg
+            .encode_wide()
+            .any(|c| c == ' ' as u16 || c == '\t' as u16);
+    if quote {
+        line.push('"' as u16);
+    }
+    let mut backslashes = 0;
+    for c in arg.encode_wide() {
+        if c == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "argument contains a nul character",
+            ));
+        }
+        if c == '\\' as u16 {
+            backslashes += 1;
+        } else {
+            // Backslashes are only special right before a quote, where each
+            // one needs escaping, along with the quote.
+            if c == '"' as u16 {
+                line.extend((0..backslashes + 1).map(|_| '\\' as u16));
+            }
+            backslashes = 0;
+        }
+        line.push(c);
+    }
+    if quote {
+        // Same for backslashes right before the closing quote.
+        line.extend((0..backslashes).map(|_| '\\' as u16));
+        line.push('"' as u16);
+    }
+    Ok(())
+}
+
+// The parent's environment with the list's changes on top, in the sorted
+// form CreateProcess wants. Names compare case-insensitively.
+fn environment_block(list: &HandleList, extra: &[(OsString, OsString)]) -> io::Result<Vec<u16>> {
+    let mut vars: BTreeMap<String, (OsString, OsString)> = BTreeMap::new();
+    let key = |name: &OsStr| name.to_string_lossy().to_uppercase();
+    if !list.env_clear {
+        for (name, value) in env::vars_os() {
+            vars.insert(key(&name), (name, value));
+        }
+    }
+    for (name, value) in &list.envs {
+        match value {
+            Some(value) => vars.insert(key(name), (name.clone(), value.clone())),
+            None => vars.remove(&key(name)),
+        };
+    }
+    for (name, value) in extra {
+        vars.insert(key(name), (name.clone(), value.clone()));
+    }
+    let mut block = Vec::new();
+    for (name, value) in vars.values() {
+        // A leading = is allowed, for the hidden variables like =C: that
+        // hold the current directory of each drive.
+        if name.is_empty()
+            || name.encode_wide().skip(1).any(|c| c == '=' as u16)
+            || name.encode_wide().any(|c| c == 0)
+            || value.encode_wide().any(|c| c == 0)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid environment variable",
+            ));
+        }
+        block.extend(name.encode_wide());
+        block.push('=' as u16);
+        block.extend(value.encode_wide());
+        block.push(0);
+    }
+    // An empty block still needs both terminators.
+    if block.is_empty() {
+        block.push(0);
+    }
+    block.push(0);
+    Ok(block)
+}
+
+pub(crate) fn wait_process(process: &OwnedHandle, block: bool) -> io::Result<Option<ExitStatus>> {
+    let handle = process.as_raw_handle() as HANDLE;
+    let timeout = if block { winbase::INFINITE } else { 0 };
+    match unsafe { synchapi::WaitForSingleObject(handle, timeout) } {
+        winbase::WAIT_OBJECT_0 => {}
+        winerror::WAIT_TIMEOUT => return Ok(None),
+        _ => return Err(io::Error::last_os_error()),
+    }
+    let mut code: DWORD = 0;
+    if unsafe { processthreadsapi::GetExitCodeProcess(handle, &mut code) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(ExitStatus::from_raw(code)))
+}
+
+pub(crate) fn kill_process(process: &OwnedHandle) -> io::Result<()> {
+    let handle = process.as_raw_handle() as HANDLE;
+    if unsafe { processthreadsapi::TerminateProcess(handle, 1) } != 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    // Terminating a process that has already exited is access denied.
+    if wait_process(process, false)?.is_some() {
+        return Ok(());
+    }
+    Err(err)
+}
+
+fn to_wide(path: &Path) -> Vec<u16> {
+    path.as_os_str().encode_wide().chain(Some(0)).collect()
+}
+
+// Ask for the attributes access bits explicitly, so that set_nonblocking works
+// on the client ends too.
+pub(crate) fn open_named_pipe_reader(path: &Path) -> io::Result<File> {
+    fs::OpenOptions::new()
+        .read(true)
+        .access_mode(GENERIC_READ | FILE_WRITE_ATTRIBUTES)
+        .open(path)
+}
+
+pub(crate) fn open_named_pipe_writer(path: &Path) -> io::Result<File> {
+    fs::OpenOptions::new()
+        .write(true)
+        .access_mode(GENERIC_WRITE | FILE_READ_ATTRIBUTES)
+        .open(path)
+}
+
+// Open a client end, waiting up to `wait` for the server. A server that
+// exists but has no free instance fails with ERROR_PIPE_BUSY, which
+// WaitNamedPipe can wait out. A server that doesn't exist yet fails with
+// ERROR_FILE_NOT_FOUND, and WaitNamedPipe fails right away in that case too,
+// so retry with a backoff, like poll.
+pub(crate) fn open_named_pipe_client(
+    path: &Path,
+    write: bool,
+    wait: Option<Duration>,
+    message_mode: bool,
+) -> io::Result<File> {
+    let deadline = wait.map(|wait| Instant::now() + wait);
+    let mut sleep = Duration::from_millis(1);
+    loop {
+        let result = if write {
+            open_named_pipe_writer(path)
+        } else {
+            open_named_pipe_reader(path)
+        };
+        let err = match result {
+            Ok(file) => {
+                // The write side's mode comes from the server's pipe type.
+                if message_mode && !write {
+                    set_read_mode_message(&file)?;
+                }
+                return Ok(file);
+            }
+            Err(err) => err,
+        };
+        let now = Instant::now();
+        let remaining = match deadline {
+            Some(deadline) if now < deadline => deadline - now,
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("timed out connecting to {}: {}", path.display(), err),
+                ))
+            }
+            None => return Err(err),
+        };
+        match err.raw_os_error().map(|code| code as DWORD) {
+            Some(winerror::ERROR_PIPE_BUSY) => {
+                let ms = cmp::min(remaining.as_millis(), u128::from(DWORD::MAX - 1));
+                // Whether this times out or an instance frees up, the next
+                // open decides. Another client might take the instance first.
+                unsafe {
+                    namedpipeapi::WaitNamedPipeW(to_wide(path).as_ptr(), cmp::max(ms, 1) as DWORD);
+                }
+            }
+            Some(winerror::ERROR_FILE_NOT_FOUND) => {
+                thread::sleep(cmp::min(sleep, remaining));
+                sleep = cmp::min(sleep * 2, MAX_POLL_SLEEP);
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+fn set_read_mode_message(file: &File) -> io::Result<()> {
+    let mut mode = winbase::PIPE_READMODE_MESSAGE;
+    let ret = unsafe {
+        namedpipeapi::SetNamedPipeHandleState(
+            file.as_raw_handle() as HANDLE,
+            &mut mode,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    if ret == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(crate) fn poll(entries: &mut [PollEntry], timeout: Option<Duration>) -> io::Result<usize> {
+    // Anonymous pipes don't support overlapped IO, and the handles themselves
+    // aren't meaningful things to wait on, so the best we can do is poll
+    // PeekNamedPipe with a backoff. There's no way at all to ask whether a
+    // pipe has room for a write, so writers always count as ready.
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut sleep = Duration::from_millis(1);
+    loop {
+        let mut count = 0;
+        for entry in entries.iter_mut() {
+            entry.ready = entry.writable || peek_readable(entry.file)?;
+            if entry.ready {
+                count += 1;
+            }
+        }
+        if count > 0 {
+            return Ok(count);
+        }
+        let now = Instant::now();
+        match deadline {
+            Some(deadline) if now >= deadline => return Ok(0),
+            Some(deadline) => thread::sleep(cmp::min(sleep, deadline - now)),
+            None => thread::sleep(sleep),
+        }
+        sleep = cmp::min(sleep * 2, MAX_POLL_SLEEP);
+    }
+}
+
+// PeekNamedPipe fails with one of these once the other end is closed.
+pub(crate) fn is_peer_closed(file: &File) -> io::Result<bool> {
+    match peek_named_pipe(file, &mut []) {
+        Ok(_) => Ok(false),
+        Err(ref e)
+            if e.raw_os_error() == Some(winerror::ERROR_BROKEN_PIPE as i32)
+                || e.raw_os_error() == Some(winerror::ERROR_NO_DATA as i32)
+                || e.raw_os_error() == Some(winerror::ERROR_PIPE_NOT_CONNECTED as i32) =>
+        {
+            Ok(true)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+const MAX_POLL_SLEEP: Duration = Duration::from_millis(50);
+
+// Whether a read would return immediately, either with data or with EOF.
+fn peek_readable(file: &File) -> io::Result<bool> {
+    match peek_named_pipe(file, &mut []) {
+        Ok((_, available)) => Ok(available > 0),
+        Err(ref e) if e.raw_os_error() == Some(winerror::ERROR_BROKEN_PIPE as i32) => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+// Returns the number of bytes copied into buf, and the total number of bytes
+// available. EOF is an ERROR_BROKEN_PIPE error.
+fn peek_named_pipe(file: &File, buf: &mut [u8]) -> io::Result<(usize, usize)> {
+    let mut read: DWORD = 0;
+    let mut available: DWORD = 0;
+    let ret = unsafe {
+        namedpipeapi::PeekNamedPipe(
+            file.as_raw_handle() as HANDLE,
+            buf.as_mut_ptr() as _,
+            cmp::min(buf.len(), MAX_IO_SIZE) as DWORD,
+            &mut read,
+            &mut available,
+            ptr::null_mut(),
+        )
+    };
+    if ret == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((read as usize, available as usize))
+}
+
+pub(crate) fn associate_iocp(file: &File, port: RawHandle, key: usize) -> io::Result<()> {
+    let ret = unsafe {
+        ioapiset::CreateIoCompletionPort(file.as_raw_handle() as HANDLE, port as HANDLE, key, 0)
+    };
+    if ret.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(crate) fn bytes_available(file: &File) -> io::Result<usize> {
+    match peek_named_pipe(file, &mut []) {
+        Ok((_, available)) => Ok(available),
+        Err(ref e) if e.raw_os_error() == Some(winerror::ERROR_BROKEN_PIPE as i32) => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn peek(file: &File, buf: &mut [u8]) -> io::Result<usize> {
+    match peek_named_pipe(file, buf) {
+        Ok((read, _)) => Ok(read),
+        Err(ref e) if e.raw_os_error() == Some(winerror::ERROR_BROKEN_PIPE as i32) => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn capacity(file: &File) -> io::Result<usize> {
+    let mut out_size: DWORD = 0;
+    let mut in_size: DWORD = 0;
+    let ret = unsafe {
+        namedpipeapi::GetNamedPipeInfo(
+            file.as_raw_handle() as HANDLE,
+            ptr::null_mut(),
+            &mut out_size,
+            &mut in_size,
+            ptr::null_mut(),
+        )
+    };
+    if ret == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Which of these applies depends on whether this is the server or client
+    // end of the underlying named pipe. For CreatePipe pipes they're the same.
+    Ok(cmp::max(out_size, in_size) as usize)
+}
+
+pub(crate) fn set_capacity(_file: &File, _capacity: usize) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Windows can't resize a pipe buffer after creation",
+    ))
+}
+
+pub(crate) fn splice(from: &File, to: &File, len: usize) -> io::Result<usize> {
+    ::copy_chunk(AnyHandle(from), AnyHandle(to), len)
+}
+
+// TransmitFile only sends to sockets, so pipes always take the slow path.
+// Unlike pread, seek_read moves the file position, so put it back afterwards.
+pub(crate) fn send_file(to: &File, from: &File, offset: u64, len: u64) -> io::Result<u64> {
+    let mut from_ref = from;
+    let position = from_ref.stream_position()?;
+    let result = send_file_at(to, from, offset, len);
+    from_ref.seek(io::SeekFrom::Start(position))?;
+    result
+}
+
+fn send_file_at(to: &File, from: &File, offset: u64, len: u64) -> io::Result<u64> {
+    use std::os::windows::fs::FileExt;
+
+    let mut buf = vec![0; cmp::min(len, 1 << 16) as usize];
+    let mut sent = 0;
+    while sent < len {
+        let count = cmp::min(len - sent, buf.len() as u64) as usize;
+        let n = match from.seek_read(&mut buf[..count], offset + sent) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            // Reading past the end of a file is an error on Windows.
+            Err(ref e) if e.raw_os_error() == Some(winerror::ERROR_HANDLE_EOF as i32) => break,
+            Err(e) => return Err(e),
+        };
+        AnyHandle(to).write_all(&buf[..n])?;
+        sent += n as u64;
+    }
+    Ok(sent)
+}
+
+pub(crate) fn copy<R: AsHandle, W: AsHandle>(from: &R, to: &W) -> io::Result<u64> {
+    let from =
+        ManuallyDrop::new(unsafe { File::from_raw_handle(from.as_handle().as_raw_handle()) });
+    let to = ManuallyDrop::new(unsafe { File::from_raw_handle(to.as_handle().as_raw_handle()) });
+    io::copy(&mut AnyHandle(&from), &mut AnyHandle(&to))
+}
+
+// The pipe side of a copy might be overlapped, which the standard library
+// can't handle, so copies go through read and write above, which pick the
+// right way to do IO for each handle.
+struct AnyHandle<'a>(&'a File);
+
+impl<'a> Read for AnyHandle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read(self.0, buf)
+    }
+}
+
+impl<'a> Write for AnyHandle<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write(self.0, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut file_ref = self.0;
+        file_ref.flush()
+    }
+}
+
+pub(crate) fn tee(_from: &File, _to: &File, _len: usize) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "tee is only available on Linux",
+    ))
+}
+
+// Not in winapi. Whether a handle was opened with FILE_FLAG_OVERLAPPED is
+// only visible through the native API, as the absence of both synchronous
+// modes.
+#[cfg(any(feature = "mio", feature = "tokio"))]
+#[repr(C)]
+struct IoStatusBlock {
+    status: usize,
+    information: usize,
+}
+
+#[cfg(any(feature = "mio", feature = "tokio"))]
+const FILE_MODE_INFORMATION: u32 = 16;
+#[cfg(any(feature = "mio", feature = "tokio"))]
+const FILE_SYNCHRONOUS_IO_ALERT: u32 = 0x10;
+#[cfg(any(feature = "mio", feature = "tokio"))]
+const FILE_SYNCHRONOUS_IO_NONALERT: u32 = 0x20;
+
+#[cfg(any(feature = "mio", feature = "tokio"))]
+#[link(name = "ntdll")]
+extern "system" {
+    fn NtQueryInformationFile(
+        handle: HANDLE,
+        io_status: *mut IoStatusBlock,
+        info: *mut c_void,
+        len: u32,
+        class: u32,
+    ) -> i32;
+    fn RtlNtStatusToDosError(status: i32) -> u32;
+}
+
+#[cfg(any(feature = "mio", feature = "tokio"))]
+fn is_overlapped(file: &File) -> io::Result<bool> {
+    let mut io_status = IoStatusBlock {
+        status: 0,
+        information: 0,
+    };
+    let mut mode: u32 = 0;
+    let status = unsafe {
+        NtQueryInformationFile(
+            file.as_raw_handle() as HANDLE,
+            &mut io_status,
+            &mut mode as *mut u32 as *mut c_void,
+            mem::size_of::<u32>() as u32,
+            FILE_MODE_INFORMATION,
+        )
+    };
+    if status < 0 {
+        let code = unsafe { RtlNtStatusToDosError(status) };
+        return Err(io::Error::from_raw_os_error(code as i32));
+    }
+    Ok(mode & (FILE_SYNCHRONOUS_IO_ALERT | FILE_SYNCHRONOUS_IO_NONALERT) == 0)
+}
+
+#[cfg(any(feature = "mio", feature = "tokio"))]
+pub(crate) fn check_overlapped(file: &File) -> io::Result<()> {
+    if !is_overlapped(file)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the pipe isn't open for overlapped I/O, see PipeOptions::overlapped",
+        ));
+    }
+    Ok(())
+}
+
 impl IntoRawHandle for PipeReader {
     fn into_raw_handle(self) -> RawHandle {
         self.0.into_raw_handle()
@@ -58,6 +1483,24 @@ impl AsRawHandle for PipeReader {
     }
 }
 
+impl AsHandle for PipeReader {
+    fn as_handle(&self) -> BorrowedHandle<'_> {
+        self.0.as_handle()
+    }
+}
+
+impl From<OwnedHandle> for PipeReader {
+    fn from(handle: OwnedHandle) -> PipeReader {
+        PipeReader(File::from(handle))
+    }
+}
+
+impl From<PipeReader> for OwnedHandle {
+    fn from(p: PipeReader) -> OwnedHandle {
+        p.0.into()
+    }
+}
+
 impl FromRawHandle for PipeReader {
     unsafe fn from_raw_handle(handle: RawHandle) -> PipeReader {
         PipeReader(File::from_raw_handle(handle))
@@ -76,8 +1519,347 @@ impl AsRawHandle for PipeWriter {
     }
 }
 
+impl AsHandle for PipeWriter {
+    fn as_handle(&self) -> BorrowedHandle<'_> {
+        self.0.as_handle()
+    }
+}
+
+impl From<OwnedHandle> for PipeWriter {
+    fn from(handle: OwnedHandle) -> PipeWriter {
+        PipeWriter(File::from(handle))
+    }
+}
+
+impl From<PipeWriter> for OwnedHandle {
+    fn from(p: PipeWriter) -> OwnedHandle {
+        p.0.into()
+    }
+}
+
 impl FromRawHandle for PipeWriter {
     unsafe fn from_raw_handle(handle: RawHandle) -> PipeWriter {
         PipeWriter(File::from_raw_handle(handle))
     }
 }
+
+impl IntoRawHandle for Duplex {
+    fn into_raw_handle(self) -> RawHandle {
+        self.0.into_raw_handle()
+    }
+}
+
+impl AsRawHandle for Duplex {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0.as_raw_handle()
+    }
+}
+
+impl AsHandle for Duplex {
+    fn as_handle(&self) -> BorrowedHandle<'_> {
+        self.0.as_handle()
+    }
+}
+
+impl From<OwnedHandle> for Duplex {
+    fn from(handle: OwnedHandle) -> Duplex {
+        Duplex(File::from(handle))
+    }
+}
+
+impl From<Duplex> for OwnedHandle {
+    fn from(p: Duplex) -> OwnedHandle {
+        p.0.into()
+    }
+}
+
+impl FromRawHandle for Duplex {
+    unsafe fn from_raw_handle(handle: RawHandle) -> Duplex {
+        Duplex(File::from_raw_handle(handle))
+    }
+}
+
+// With the "mio" feature, an overlapped pipe end can become a mio NamedPipe,
+// which does the IOCP bookkeeping that mio needs on Windows. Pipe ends can't be
+// sources themselves, because mio doesn't expose that machinery for handles.
+#[cfg(feature = "mio")]
+macro_rules! impl_mio_named_pipe {
+    ($t:ident) => {
+        /// This requires the `mio` Cargo feature. It fails with `InvalidInput`
+        /// unless the handle was opened for overlapped I/O, for example with
+        /// [`PipeOptions::overlapped`](struct.PipeOptions.html#method.overlapped),
+        /// and the pipe is closed in that case.
+        impl TryFrom<$t> for mio::windows::NamedPipe {
+            type Error = io::Error;
+
+            fn try_from(pipe: $t) -> io::Result<mio::windows::NamedPipe> {
+                check_overlapped(&pipe.0)?;
+                Ok(unsafe { mio::windows::NamedPipe::from_raw_handle(pipe.into_raw_handle()) })
+            }
+        }
+    };
+}
+
+#[cfg(feature = "mio")]
+impl_mio_named_pipe!(PipeReader);
+#[cfg(feature = "mio")]
+impl_mio_named_pipe!(PipeWriter);
+#[cfg(feature = "mio")]
+impl_mio_named_pipe!(Duplex);
+
+// Copy `handle` into `target_process`, and send its new value as 8 bytes of
+// ordinary data.
+pub(crate) fn send_handle(
+    socket: &File,
+    handle: BorrowedHandle,
+    target_process: BorrowedHandle,
+) -> io::Result<()> {
+    let target_process = target_process.as_raw_handle() as HANDLE;
+    let mut remote: HANDLE = ptr::null_mut();
+    let ret = unsafe {
+        handleapi::DuplicateHandle(
+            processthreadsapi::GetCurrentProcess(),
+            handle.as_raw_handle() as HANDLE,
+            target_process,
+            &mut remote,
+            0,
+            FALSE,
+            DUPLICATE_SAME_ACCESS,
+        )
+    };
+    if ret == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if let Err(e) = AnyHandle(socket).write_all(&(remote as usize as u64).to_le_bytes()) {
+        // The other side will never hear about the new handle, so close it
+        // in the target process rather than leaking it there.
+        unsafe {
+            handleapi::DuplicateHandle(
+                target_process,
+                remote,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                0,
+                FALSE,
+                DUPLICATE_CLOSE_SOURCE,
+            );
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+pub(crate) unsafe fn recv_handle(socket: &File) -> io::Result<OwnedHandle> {
+    let mut value = [0; 8];
+    AnyHandle(socket).read_exact(&mut value)?;
+    Ok(OwnedHandle::from_raw_handle(
+        u64::from_le_bytes(value) as usize as RawHandle,
+    ))
+}
+
+// Not in winapi 0.3.5, which is the oldest version this allows. ConPTY needs
+// Windows 10 1809 or later.
+type Hpcon = *mut c_void;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Coord {
+    x: i16,
+    y: i16,
+}
+
+// ProcThreadAttributeValue(22, FALSE, TRUE, FALSE).
+const PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE: usize = 0x0002_0016;
+
+// Linking these directly would keep every program using this crate from
+// starting on older Windows, even if it never makes a pty, so look them up
+// the first time they're needed instead.
+struct ConPty {
+    create: CreatePseudoConsole,
+    resize: ResizePseudoConsole,
+    close: ClosePseudoConsole,
+}
+
+type CreatePseudoConsole =
+    unsafe extern "system" fn(Coord, HANDLE, HANDLE, DWORD, *mut Hpcon) -> HRESULT;
+type ResizePseudoConsole = unsafe extern "system" fn(Hpcon, Coord) -> HRESULT;
+type ClosePseudoConsole = unsafe extern "system" fn(Hpcon);
+
+fn conpty() -> io::Result<&'static ConPty> {
+    static CONPTY: OnceLock<Option<ConPty>> = OnceLock::new();
+    let functions = CONPTY.get_or_init(|| unsafe {
+        let kernel32 = libloaderapi::GetModuleHandleW(to_wide(Path::new("kernel32")).as_ptr());
+        if kernel32.is_null() {
+            return None;
+        }
+        let create = libloaderapi::GetProcAddress(kernel32, b"CreatePseudoConsole\0".as_ptr() as _);
+        let resize = libloaderapi::GetProcAddress(kernel32, b"ResizePseudoConsole\0".as_ptr() as _);
+        let close = libloaderapi::GetProcAddress(kernel32, b"ClosePseudoConsole\0".as_ptr() as _);
+        if create.is_null() || resize.is_null() || close.is_null() {
+            return None;
+        }
+        Some(ConPty {
+            create: mem::transmute::<FARPROC, CreatePseudoConsole>(create),
+            resize: mem::transmute::<FARPROC, ResizePseudoConsole>(resize),
+            close: mem::transmute::<FARPROC, ClosePseudoConsole>(close),
+        })
+    });
+    functions.as_ref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "pseudoconsoles need Windows 10 1809 or later",
+        )
+    })
+}
+
+// ConPTY reports Win32 errors wrapped in an HRESULT, as 0x8007xxxx.
+fn hresult_error(hr: HRESULT) -> io::Error {
+    if (hr as u32) & 0xFFFF_0000 == 0x8007_0000 {
+        io::Error::from_raw_os_error(hr & 0xFFFF)
+    } else {
+        io::Error::from_raw_os_error(hr)
+    }
+}
+
+// ConPTY needs a size up front, and it has no way to ask for it later.
+const DEFAULT_PTY_SIZE: (u16, u16) = (24, 80);
+
+fn pty_coord(rows: u16, cols: u16) -> io::Result<Coord> {
+    if rows == 0 || cols == 0 || rows > i16::MAX as u16 || cols > i16::MAX as u16 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid pseudoconsole size",
+        ));
+    }
+    Ok(Coord {
+        x: cols as i16,
+        y: rows as i16,
+    })
+}
+
+#[derive(Debug)]
+struct PseudoConsole {
+    handle: Hpcon,
+    size: Mutex<(u16, u16)>,
+}
+
+// An HPCON isn't tied to the thread that created it.
+unsafe impl Send for PseudoConsole {}
+unsafe impl Sync for PseudoConsole {}
+
+impl Drop for PseudoConsole {
+    fn drop(&mut self) {
+        // A PseudoConsole only exists if conpty() worked before.
+        if let Ok(conpty) = conpty() {
+            unsafe {
+                (conpty.close)(self.handle);
+            }
+        }
+    }
+}
+
+// A ConPTY has no slave end. Children are attached to the console itself with
+// a startup attribute (see spawn_with_handle_list), and it talks to the
+// master over two ordinary pipes. The master only holds a weak reference to
+// the console, because the output pipe only reaches EOF once the console is
+// closed, which happens when the last slave is dropped.
+#[derive(Debug)]
+pub(crate) struct PtyMasterInner {
+    output: File,
+    input: File,
+    console: Weak<PseudoConsole>,
+}
+
+impl PtyMasterInner {
+    pub(crate) fn try_clone(&self) -> io::Result<PtyMasterInner> {
+        Ok(PtyMasterInner {
+            output: self.output.try_clone()?,
+            input: self.input.try_clone()?,
+            console: self.console.clone(),
+        })
+    }
+
+    fn console(&self) -> io::Result<Arc<PseudoConsole>> {
+        self.console.upgrade().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the pseudoconsole has been closed",
+            )
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PtySlaveInner(Arc<PseudoConsole>);
+
+impl PtySlaveInner {
+    pub(crate) fn try_clone(&self) -> io::Result<PtySlaveInner> {
+        Ok(PtySlaveInner(self.0.clone()))
+    }
+}
+
+pub(crate) fn openpty() -> io::Result<(PtyMasterInner, PtySlaveInner)> {
+    let conpty = conpty()?;
+    let (input_reader, input_writer) = ::pipe()?;
+    let (output_reader, output_writer) = ::pipe()?;
+    let (rows, cols) = DEFAULT_PTY_SIZE;
+    let mut handle: Hpcon = ptr::null_mut();
+    let hr = unsafe {
+        (conpty.create)(
+            pty_coord(rows, cols)?,
+            input_reader.as_raw_handle() as HANDLE,
+            output_writer.as_raw_handle() as HANDLE,
+            0,
+            &mut handle,
+        )
+    };
+    if hr < 0 {
+        return Err(hresult_error(hr));
+    }
+    // The console has its own copies of its ends of the pipes now.
+    drop(input_reader);
+    drop(output_writer);
+    let console = Arc::new(PseudoConsole {
+        handle,
+        size: Mutex::new((rows, cols)),
+    });
+    let master = PtyMasterInner {
+        output: output_reader.0,
+        input: input_writer.0,
+        console: Arc::downgrade(&console),
+    };
+    Ok((master, PtySlaveInner(console)))
+}
+
+pub(crate) fn read_pty(master: &PtyMasterInner, buf: &mut [u8]) -> io::Result<usize> {
+    read(&master.output, buf)
+}
+
+pub(crate) fn write_pty(master: &PtyMasterInner, buf: &[u8]) -> io::Result<usize> {
+    write(&master.input, buf)
+}
+
+pub(crate) fn window_size(master: &PtyMasterInner) -> io::Result<(u16, u16)> {
+    let console = master.console()?;
+    let size = *console.size.lock().unwrap();
+    Ok(size)
+}
+
+pub(crate) fn set_window_size(master: &PtyMasterInner, rows: u16, cols: u16) -> io::Result<()> {
+    let console = master.console()?;
+    let mut size = console.size.lock().unwrap();
+    let conpty = conpty()?;
+    let hr = unsafe { (conpty.resize)(console.handle, pty_coord(rows, cols)?) };
+    if hr < 0 {
+        return Err(hresult_error(hr));
+    }
+    *size = (rows, cols);
+    Ok(())
+}
+
+pub(crate) fn attach_pty(_command: &mut Command, _slave: &PtySlaveInner) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "a Command can't be attached to a pseudoconsole, use HandleList::pty",
+    ))
+}